@@ -0,0 +1,224 @@
+//! Tracking of ANSI escape sequences (SGR attributes like colors) in the output
+
+use std::fmt;
+
+/// A foreground color set by an SGR escape sequence, e.g. `\x1b[31m` for red
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+    /// one of the 256 colors, `\x1b[38;5;<n>m`
+    Indexed(u8),
+    /// true color, `\x1b[38;2;<r>;<g>;<b>m`
+    Rgb(u8, u8, u8),
+}
+
+const BASIC: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Yellow,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::White,
+    Color::BrightBlack, Color::BrightRed, Color::BrightGreen, Color::BrightYellow,
+    Color::BrightBlue, Color::BrightMagenta, Color::BrightCyan, Color::BrightWhite,
+];
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A char which ends up on the screen, with its position in the raw output
+/// and the foreground color it is printed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StyledChar {
+    /// byte position in the raw output
+    pub pos: usize,
+    pub c: char,
+    pub fg: Option<Color>,
+}
+
+/// Split `raw` output into the printed chars (with their color), dropping all escape
+/// sequences. Only SGR sequences (`\x1b[...m`) change the color, other CSI sequences
+/// like cursor movement are just skipped.
+pub fn styled_chars(raw: &str) -> Vec<StyledChar> {
+    let mut res = Vec::with_capacity(raw.len());
+    let mut fg = None;
+    let mut chars = raw.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        if c != '\u{1b}' {
+            res.push(StyledChar { pos, c, fg });
+            continue;
+        }
+        if chars.peek().map(|&(_, c)| c) != Some('[') {
+            // not a CSI sequence: skip ESC and the following char
+            chars.next();
+            continue;
+        }
+        chars.next();
+        let mut params = String::new();
+        for (_, c) in chars.by_ref() {
+            if ('\u{40}'..='\u{7e}').contains(&c) {
+                if c == 'm' {
+                    fg = apply_sgr(&params, fg);
+                }
+                break;
+            }
+            params.push(c);
+        }
+    }
+    res
+}
+
+/// Remove all escape sequences from `raw`, e.g. colors and cursor movements
+pub fn strip_escapes(raw: &str) -> String {
+    styled_chars(raw).into_iter().map(|sc| sc.c).collect()
+}
+
+/// Removes the escape sequences from output which arrives in pieces, a sequence may be
+/// split between two `push` calls. Unlike `strip_escapes` this also drops OSC
+/// sequences (e.g. window titles) and strings (DCS, APC, PM).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EscapeFilter {
+    state: FilterState,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum FilterState {
+    #[default]
+    Text,
+    // after ESC
+    Escape,
+    // ESC followed by intermediate bytes, e.g. `\x1b(B`
+    Intermediate,
+    // `\x1b[` up to the final byte
+    Csi,
+    // OSC and other strings up to BEL or ST (`\x1b\\`)
+    String,
+    // ESC within a string
+    StringEscape,
+}
+
+impl EscapeFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the printed text of `input` to `out`
+    pub fn push(&mut self, input: &str, out: &mut String) {
+        use self::FilterState::*;
+        for c in input.chars() {
+            self.state = match (self.state, c) {
+                (Text, '\u{1b}') => Escape,
+                (Text, c) => {
+                    out.push(c);
+                    Text
+                }
+                (Escape, '[') => Csi,
+                (Escape, ']') | (Escape, 'P') | (Escape, '_') | (Escape, '^') => String,
+                (Escape, ' '..='/') | (Intermediate, ' '..='/') => Intermediate,
+                (Escape, _) | (Intermediate, _) => Text,
+                (Csi, '\u{40}'..='\u{7e}') => Text,
+                (Csi, _) => Csi,
+                (String, '\u{7}') | (StringEscape, '\\') => Text,
+                (String, '\u{1b}') => StringEscape,
+                (String, _) | (StringEscape, _) => String,
+            };
+        }
+    }
+}
+
+// returns the foreground color after applying SGR `params` (e.g. "1;31")
+fn apply_sgr(params: &str, mut fg: Option<Color>) -> Option<Color> {
+    let codes: Vec<u32> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 | 39 => fg = None,
+            n @ 30..=37 => fg = Some(BASIC[(n - 30) as usize]),
+            n @ 90..=97 => fg = Some(BASIC[(n - 90 + 8) as usize]),
+            38 => match codes.get(i + 1) {
+                Some(5) => {
+                    fg = codes.get(i + 2).map(|&n| Color::Indexed(n as u8));
+                    i += 2;
+                }
+                Some(2) => {
+                    let c = |j| codes.get(i + j).copied().unwrap_or(0) as u8;
+                    fg = Some(Color::Rgb(c(2), c(3), c(4)));
+                    i += 4;
+                }
+                _ => {}
+            },
+            // background colors with extended parameters, skip them
+            48 => i += match codes.get(i + 1) { Some(5) => 2, Some(2) => 4, _ => 0 },
+            _ => {}
+        }
+        i += 1;
+    }
+    fg
+}
+
+/// Find `text` printed in foreground `color` within the raw output, return the byte
+/// positions within `raw` of the start of the text and after its end
+pub fn find_colored(text: &str, color: Color, raw: &str) -> Option<(usize, usize)> {
+    let needle: Vec<char> = text.chars().collect();
+    if needle.is_empty() {
+        return None;
+    }
+    let styled = styled_chars(raw);
+    styled.windows(needle.len())
+        .find(|w| w.iter().zip(&needle).all(|(sc, c)| sc.c == *c && sc.fg == Some(color)))
+        .map(|w| {
+            let last = w[w.len() - 1];
+            (w[0].pos, last.pos + last.c.len_utf8())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_colored() {
+        let raw = "ERROR in plain, \x1b[1;31mERROR\x1b[0m in red";
+        assert_eq!(Some((23, 28)), find_colored("ERROR", Color::Red, raw));
+        assert_eq!(None, find_colored("ERROR", Color::Green, raw));
+        assert_eq!(None, find_colored("in red", Color::Red, raw));
+    }
+
+    #[test]
+    fn test_extended_colors() {
+        let raw = "\x1b[38;5;208mwarn\x1b[39m \x1b[38;2;0;255;0mok\x1b[m";
+        assert!(find_colored("warn", Color::Indexed(208), raw).is_some());
+        assert!(find_colored("ok", Color::Rgb(0, 255, 0), raw).is_some());
+    }
+
+    #[test]
+    fn test_strip_escapes() {
+        assert_eq!("hello world", strip_escapes("\x1b[2J\x1b[32mhello\x1b[0m world"));
+    }
+
+    #[test]
+    fn test_escape_filter() {
+        let mut filter = EscapeFilter::new();
+        let mut out = String::new();
+        let pieces = ["\x1b[?25l\x1b[1;1H\x1b", "[Kh", "ello\x1b]0;title\x07 wo",
+                      "\x1b(Brld\x1b]2;", "x\x1b\\!\r\n"];
+        for piece in &pieces {
+            filter.push(piece, &mut out);
+        }
+        assert_eq!("hello world!\r\n", out);
+    }
+}
@@ -0,0 +1,764 @@
+//! Async sessions (needs the `async-core` feature, `async` for tokio)
+//!
+//! Other than `PtySession` no reader thread is spawned: the pty is switched to
+//! non-blocking mode and registered with the reactor of the runtime, so thousands of
+//! sessions can run concurrently on a few threads.
+//!
+//! The session only needs the pty as `futures_io::AsyncRead + AsyncWrite` and a `Timer`
+//! for the timeouts, it doesn't spawn tasks. With the `async` feature both are provided
+//! for tokio (`spawn_async`). For other runtimes register the handle returned by
+//! `nonblocking_handle` with the runtime's reactor (e.g. `smol::Async::new`) and pass it
+//! to `AsyncPtySession::with_runtime` together with a `Timer` based on the runtime's timer.
+
+use crate::control_code::ControlCode;
+use crate::process::PtyProcess;
+use crate::reader::{self, ReadUntil, Regex};
+use futures_core::Stream;
+use futures_io::{AsyncRead, AsyncWrite};
+use nix::libc::EIO;
+use std::fs::File;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time;
+#[cfg(feature = "async")]
+use std::io::{Read, Write};
+#[cfg(feature = "async")]
+use std::process::Command;
+#[cfg(feature = "async")]
+use nix::sys::termios;
+#[cfg(feature = "async")]
+use tokio::io::unix::AsyncFd;
+use crate::errors::*; // load error-chain
+
+/// The timer of the async runtime, used for the timeouts of `AsyncPtySession`
+pub trait Timer {
+    /// A future which is ready after `duration`
+    fn sleep(&self, duration: time::Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// `Timer` of tokio
+#[cfg(feature = "async")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioTimer;
+
+#[cfg(feature = "async")]
+impl Timer for TokioTimer {
+    fn sleep(&self, duration: time::Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Open a new handle to the pty of `process` in non-blocking mode, to be registered
+/// with the reactor of an async runtime
+pub fn nonblocking_handle(process: &PtyProcess) -> Result<File> {
+    process.set_nonblocking(true)?;
+    Ok(process.get_file_handle())
+}
+
+/// The master side of the pty in non-blocking mode, registered with the tokio reactor
+/// via `AsyncFd`: reads are driven by readiness events, there's no helper thread.
+///
+/// Implements tokio's `AsyncRead` and `AsyncWrite`, so it can be plugged into codecs
+/// (`tokio_util::codec::Framed`) or split into reader and writer with `tokio::io::split`,
+/// as well as the `futures_io` versions of them.
+/// Reading returns EOF (0 bytes) once the process closed the terminal.
+#[cfg(feature = "async")]
+pub struct AsyncPty {
+    inner: AsyncFd<File>,
+}
+
+#[cfg(feature = "async")]
+impl AsyncPty {
+    /// Open a new handle to the pty of `process`, switch it into non-blocking mode and
+    /// register it with the reactor of the current tokio runtime
+    pub fn new(process: &PtyProcess) -> Result<AsyncPty> {
+        let file = nonblocking_handle(process)?;
+        let inner = AsyncFd::new(file).chain_err(|| "cannot register pty with tokio")?;
+        Ok(AsyncPty { inner })
+    }
+
+    fn poll_read_slice(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        eio_is_eof(|| self.poll_read_once(cx, buf))
+    }
+
+    fn poll_read_once(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_read_ready(cx))?;
+            match guard.try_io(|inner| inner.get_ref().read(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_write_slice(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        loop {
+            let mut guard = ready!(self.inner.poll_write_ready(cx))?;
+            match guard.try_io(|inner| inner.get_ref().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+// EIO: the other side of the pty is closed. Linux may report it before the last output
+// is readable, so read once more: only a second EIO in a row is EOF
+fn eio_is_eof(mut read: impl FnMut() -> Poll<io::Result<usize>>) -> Poll<io::Result<usize>> {
+    match ready!(read()) {
+        Err(ref e) if e.raw_os_error() == Some(EIO) => match ready!(read()) {
+            Err(ref e) if e.raw_os_error() == Some(EIO) => Poll::Ready(Ok(0)),
+            result => Poll::Ready(result),
+        },
+        result => Poll::Ready(result),
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncRead for AsyncPty {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut tokio::io::ReadBuf<'_>)
+                 -> Poll<io::Result<()>> {
+        let n = ready!(self.poll_read_slice(cx, buf.initialize_unfilled()))?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl tokio::io::AsyncWrite for AsyncPty {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<io::Result<usize>> {
+        self.poll_write_slice(cx, buf)
+    }
+
+    /// writes aren't buffered, nothing to flush
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    /// a pty can't be closed for writing only, see `PtySession::send_eof`
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncRead for AsyncPty {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<io::Result<usize>> {
+        self.poll_read_slice(cx, buf)
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncWrite for AsyncPty {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<io::Result<usize>> {
+        self.poll_write_slice(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// `AsyncPtySession` on tokio, see `spawn_async`
+#[cfg(feature = "async")]
+pub type TokioPtySession = AsyncPtySession<AsyncPty, TokioTimer>;
+
+/// Async version of `PtySession`. `P` is the pty registered with the runtime,
+/// `T` the runtime's timer (see the module docs).
+pub struct AsyncPtySession<P, T> {
+    pub process: PtyProcess,
+    pty: P,
+    timer: T,
+    buffer: String,
+    eof: bool,
+    timeout: Option<time::Duration>,
+}
+
+impl<P, T> AsyncPtySession<P, T>
+    where P: AsyncRead + AsyncWrite + Unpin,
+          T: Timer
+{
+    /// Create a session for any runtime: `pty` is the handle returned by
+    /// `nonblocking_handle(&process)`, registered with the runtime's reactor.
+    /// `timeout_ms` is the timeout for every `exp_*` call.
+    pub fn with_runtime(mut process: PtyProcess, pty: P, timer: T, timeout_ms: Option<u64>)
+                        -> Self {
+        process.set_kill_timeout(timeout_ms);
+        AsyncPtySession {
+            process,
+            pty,
+            timer,
+            buffer: String::with_capacity(1024),
+            eof: false,
+            timeout: timeout_ms.map(time::Duration::from_millis),
+        }
+    }
+
+    /// Send string to process. Unlike `PtySession::send` there's no buffering,
+    /// so no flush is needed. Returns number of written bytes
+    pub async fn send(&mut self, s: &str) -> Result<usize> {
+        write_all(&mut self.pty, s.as_bytes())
+            .await
+            .chain_err(|| "cannot write line to process")?;
+        Ok(s.len())
+    }
+
+    /// Send string and a newline to process. Returns number of written bytes
+    pub async fn send_line(&mut self, line: &str) -> Result<usize> {
+        let len = self.send(line).await?;
+        Ok(len + self.send("\n").await?)
+    }
+
+    /// Wait until provided string is seen on stdout of child process.
+    /// Return the yet unread output (without the matched string)
+    pub async fn exp_string(&mut self, needle: &str) -> Result<String> {
+        self.exp(&ReadUntil::String(needle.to_string())).await.map(|(s, _)| s)
+    }
+
+    /// Wait until provided regex is seen on stdout of child process.
+    /// Return a tuple:
+    /// 1. the yet unread output
+    /// 2. the matched regex
+    pub async fn exp_regex(&mut self, regex: &str) -> Result<(String, String)> {
+        let regex = Regex::new(regex).chain_err(|| "invalid regex")?;
+        self.exp(&ReadUntil::Regex(regex)).await
+    }
+
+    /// Wait until any of the provided needles is found, see `PtySession::exp_any`
+    pub async fn exp_any(&mut self, needles: Vec<ReadUntil>) -> Result<(String, String)> {
+        self.exp(&ReadUntil::Any(needles)).await
+    }
+
+    /// Wait until we see EOF (i.e. child process has terminated)
+    /// Return all the yet unread output
+    pub async fn exp_eof(&mut self) -> Result<String> {
+        self.exp(&ReadUntil::EOF).await.map(|(_, s)| s)
+    }
+
+    /// Read one line and return line without the newline (and \r at the end if present)
+    pub async fn read_line(&mut self) -> Result<String> {
+        let (mut line, _) = self.exp(&ReadUntil::String('\n'.to_string())).await?;
+        if line.ends_with('\r') {
+            line.pop();
+        }
+        Ok(line)
+    }
+
+    /// The output as a stream of lines (without the newline and \r at the end), so it
+    /// can be used with stream combinators. A last line without newline is returned at
+    /// EOF. The timeout of the session doesn't apply, use combinators for that.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use futures::StreamExt;
+    /// use rexpect::async_session::spawn_async;
+    /// use std::process::Command;
+    /// # use rexpect::errors::*;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    ///     # async {
+    /// let mut cmd = Command::new("bash");
+    /// cmd.args(["-c", "seq 1 10"]);
+    /// let mut p = spawn_async(cmd, Some(1000))?;
+    /// let lines: Vec<String> = p.lines()
+    ///     .take_while(|l| std::future::ready(l.as_deref().ok() != Some("3")))
+    ///     .map(|l| l.unwrap())
+    ///     .collect()
+    ///     .await;
+    /// assert_eq!(vec!["1", "2"], lines);
+    ///         # Ok::<(), Error>(())
+    ///     # }.await.expect("test failed");
+    /// # }
+    /// ```
+    pub fn lines(&mut self) -> Lines<'_, P, T> {
+        Lines { session: self }
+    }
+
+    /// The output as a stream of byte chunks as they are read from the process,
+    /// starting with the output which is already buffered. See `lines`.
+    pub fn chunks(&mut self) -> Chunks<'_, P, T> {
+        Chunks { session: self }
+    }
+
+    /// Hand the session over to the user: everything read from `input` is sent to the
+    /// process and its output is written to `output`, until the process ends or the
+    /// `escape` key is read (which is not sent). Output which was already read but not
+    /// consumed by `exp_*` calls is written first. When `input` ends the output is still
+    /// passed on until the process ends.
+    ///
+    /// See `interact` for the terminal of the user with tokio.
+    pub async fn interact_with<I, O>(&mut self, mut input: I, mut output: O,
+                                     escape: Option<ControlCode>) -> Result<()>
+        where I: AsyncRead + Unpin,
+              O: AsyncWrite + Unpin
+    {
+        enum Event {
+            Input(usize),
+            Output(bool),
+        }
+        let escape = escape.map(u8::from);
+        let mut buf = [0u8; 1024];
+        let mut input_open = true;
+        loop {
+            if !self.buffer.is_empty() {
+                write_all(&mut output, &reader::to_bytes(&self.buffer))
+                    .await
+                    .chain_err(|| "cannot write output")?;
+                poll_fn(|cx| Pin::new(&mut output).poll_flush(cx))
+                    .await
+                    .chain_err(|| "cannot write output")?;
+                self.buffer.clear();
+            }
+            let event = poll_fn(|cx| {
+                if input_open {
+                    if let Poll::Ready(read) = Pin::new(&mut input).poll_read(cx, &mut buf) {
+                        return Poll::Ready(read.map(Event::Input)
+                            .chain_err(|| "cannot read input"));
+                    }
+                }
+                self.poll_fill(cx).map_ok(Event::Output)
+            })
+                .await?;
+            match event {
+                Event::Input(0) => input_open = false,
+                Event::Input(n) => {
+                    let data = &buf[..n];
+                    let end = escape.and_then(|e| data.iter().position(|&c| c == e));
+                    write_all(&mut self.pty, &data[..end.unwrap_or(n)])
+                        .await
+                        .chain_err(|| "cannot write to process")?;
+                    if end.is_some() {
+                        return Ok(());
+                    }
+                }
+                Event::Output(true) => {}
+                Event::Output(false) => return Ok(()),
+            }
+        }
+    }
+
+    // read more output into the buffer, `false` at EOF
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool>> {
+        if self.eof {
+            return Poll::Ready(Ok(false));
+        }
+        let mut buf = [0u8; 1024];
+        let n = ready!(eio_is_eof(|| Pin::new(&mut self.pty).poll_read(cx, &mut buf)))
+            .chain_err(|| "cannot read from process")?;
+        self.eof = n == 0;
+        self.buffer.push_str(&reader::from_bytes(&buf[..n]));
+        Poll::Ready(Ok(!self.eof))
+    }
+
+    /// The pty of the process, e.g. to use it with codecs. Note that output which was
+    /// already read by `exp_*` calls is not returned again.
+    pub fn pty(&mut self) -> &mut P {
+        &mut self.pty
+    }
+
+    // the async version of `NBReader::read_until`, the timeout is driven by the timer
+    async fn exp(&mut self, needle: &ReadUntil) -> Result<(String, String)> {
+        let mut sleep = self.timeout.map(|t| self.timer.sleep(t));
+        loop {
+            if let Some((start, end)) = reader::find(needle, &self.buffer, self.eof) {
+                let first = self.buffer.drain(..start).collect();
+                let second = self.buffer.drain(..end - start).collect();
+                return Ok((first, second));
+            }
+            if self.eof {
+                return Err(ErrorKind::EOF(needle.to_string(),
+                                          self.buffer.clone(),
+                                          self.process.status())
+                                   .into());
+            }
+            let timed_out = poll_fn(|cx| {
+                if let Some(ref mut sleep) = sleep {
+                    if sleep.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Ok(true));
+                    }
+                }
+                self.poll_fill(cx).map_ok(|_| false)
+            })
+                .await?;
+            if timed_out {
+                return Err(ErrorKind::Timeout(needle.to_string(),
+                                              self.buffer.clone(),
+                                              self.timeout.unwrap_or_default())
+                                   .into());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl AsyncPtySession<AsyncPty, TokioTimer> {
+    /// Hand the session over to the user of the terminal (e.g. a TUI which temporarily
+    /// runs a subprocess), until the process ends or Ctrl-] is pressed. The terminal is
+    /// switched into raw mode meanwhile, so every key goes straight to the process.
+    ///
+    /// Note that tokio reads stdin on a blocking thread: after Ctrl-] that thread still
+    /// waits for the next key, which is then lost for the next reader of stdin.
+    pub async fn interact(&mut self) -> Result<()> {
+        let _raw = RawMode::enable(0)?;
+        self.interact_with(TokioCompat(tokio::io::stdin()),
+                           TokioCompat(tokio::io::stdout()),
+                           Some(ControlCode::GroupSeparator))
+            .await
+    }
+}
+
+// puts the terminal at `fd` into raw mode (if it is one) and restores it when dropped
+#[cfg(feature = "async")]
+struct RawMode {
+    fd: std::os::unix::io::RawFd,
+    original: Option<termios::Termios>,
+}
+
+#[cfg(feature = "async")]
+impl RawMode {
+    fn enable(fd: std::os::unix::io::RawFd) -> Result<RawMode> {
+        let original = termios::tcgetattr(fd).ok();
+        if let Some(ref original) = original {
+            let mut raw = original.clone();
+            termios::cfmakeraw(&mut raw);
+            termios::tcsetattr(fd, termios::SetArg::TCSANOW, &raw)
+                .chain_err(|| "cannot switch terminal into raw mode")?;
+        }
+        Ok(RawMode { fd, original })
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(ref original) = self.original {
+            let _ = termios::tcsetattr(self.fd, termios::SetArg::TCSANOW, original);
+        }
+    }
+}
+
+// tokio's reader and writer as `futures_io` ones
+#[cfg(feature = "async")]
+struct TokioCompat<S>(S);
+
+#[cfg(feature = "async")]
+impl<S: tokio::io::AsyncRead + Unpin> AsyncRead for TokioCompat<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                 -> Poll<io::Result<usize>> {
+        let mut read_buf = tokio::io::ReadBuf::new(buf);
+        ready!(Pin::new(&mut self.0).poll_read(cx, &mut read_buf))?;
+        Poll::Ready(Ok(read_buf.filled().len()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<S: tokio::io::AsyncWrite + Unpin> AsyncWrite for TokioCompat<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                  -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+async fn write_all<W: AsyncWrite + Unpin>(writer: &mut W, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = poll_fn(|cx| Pin::new(&mut *writer).poll_write(cx, buf)).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// Stream of output lines, see `AsyncPtySession::lines`
+pub struct Lines<'a, P, T> {
+    session: &'a mut AsyncPtySession<P, T>,
+}
+
+impl<P, T> Stream for Lines<'_, P, T>
+    where P: AsyncRead + AsyncWrite + Unpin,
+          T: Timer
+{
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let session = &mut *self.session;
+        loop {
+            if let Some(pos) = session.buffer.find('\n') {
+                let mut line: String = session.buffer.drain(..=pos).collect();
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+                return Poll::Ready(Some(Ok(line)));
+            }
+            match ready!(session.poll_fill(cx)) {
+                Ok(true) => {}
+                Ok(false) if session.buffer.is_empty() => return Poll::Ready(None),
+                Ok(false) => return Poll::Ready(Some(Ok(session.buffer.drain(..).collect()))),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+    }
+}
+
+/// Stream of output chunks, see `AsyncPtySession::chunks`
+pub struct Chunks<'a, P, T> {
+    session: &'a mut AsyncPtySession<P, T>,
+}
+
+impl<P, T> Stream for Chunks<'_, P, T>
+    where P: AsyncRead + AsyncWrite + Unpin,
+          T: Timer
+{
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let session = &mut *self.session;
+        if session.buffer.is_empty() {
+            match ready!(session.poll_fill(cx)) {
+                Ok(true) => {}
+                Ok(false) => return Poll::Ready(None),
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+        let chunk = reader::to_bytes(&session.buffer);
+        session.buffer.clear();
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+/// Start command in a pty session, like `session::spawn_command` but for use with
+/// async/await. Needs to be called within a tokio runtime.
+///
+/// `timeout_ms` is the timeout for every `exp_*` call, implemented with tokio's timer.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::async_session::spawn_async;
+/// use std::process::Command;
+/// # use rexpect::errors::*;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+///     # async {
+/// let mut p = spawn_async(Command::new("cat"), Some(1000))?;
+/// p.send_line("hello").await?;
+/// assert_eq!("hello", p.read_line().await?);
+///         # Ok::<(), Error>(())
+///     # }.await.expect("test failed");
+/// # }
+/// ```
+#[cfg(feature = "async")]
+pub fn spawn_async(command: Command, timeout_ms: Option<u64>) -> Result<TokioPtySession> {
+    let process = PtyProcess::new(command).chain_err(|| "couldn't start process")?;
+    let pty = AsyncPty::new(&process)?;
+    Ok(AsyncPtySession::with_runtime(process, pty, TokioTimer, timeout_ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::thread;
+
+    // a minimal runtime: instead of a reactor a thread wakes the task up to poll again
+    struct PolledPty(File);
+
+    fn retry_later(cx: &mut Context<'_>, result: io::Result<usize>) -> Poll<io::Result<usize>> {
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                let waker = cx.waker().clone();
+                thread::spawn(move || {
+                    thread::sleep(time::Duration::from_millis(10));
+                    waker.wake();
+                });
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        }
+    }
+
+    impl AsyncRead for PolledPty {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8])
+                     -> Poll<io::Result<usize>> {
+            retry_later(cx, (&self.0).read(buf))
+        }
+    }
+
+    impl AsyncWrite for PolledPty {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8])
+                      -> Poll<io::Result<usize>> {
+            retry_later(cx, (&self.0).write(buf))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct ThreadTimer;
+
+    impl Timer for ThreadTimer {
+        fn sleep(&self, duration: time::Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+            let (tx, rx) = futures::channel::oneshot::channel();
+            thread::spawn(move || {
+                thread::sleep(duration);
+                let _ = tx.send(());
+            });
+            Box::pin(async move {
+                let _ = rx.await;
+            })
+        }
+    }
+
+    #[test]
+    fn test_interact_with() {
+        futures::executor::block_on(async {
+            let mut cmd = std::process::Command::new("bash");
+            cmd.args(["-c", "echo started; read x; echo got $x"]);
+            let process = PtyProcess::new(cmd)?;
+            let pty = PolledPty(nonblocking_handle(&process)?);
+            let mut p = AsyncPtySession::with_runtime(process, pty, ThreadTimer, Some(1000));
+            p.exp_string("start").await?;
+            // the input ends, the output is passed on until the process ends
+            let mut output = futures::io::Cursor::new(Vec::new());
+            p.interact_with(&b"hi\n"[..], &mut output, None).await?;
+            assert_eq!("ed\r\ngot hi\r\n", String::from_utf8_lossy(output.get_ref()));
+
+            let process = PtyProcess::new(std::process::Command::new("cat"))?;
+            let pty = PolledPty(nonblocking_handle(&process)?);
+            let mut p = AsyncPtySession::with_runtime(process, pty, ThreadTimer, Some(1000));
+            let escape = Some(ControlCode::GroupSeparator);
+            p.interact_with(&b"one\n\x1dtwo\n"[..], futures::io::sink(), escape).await?;
+            // only the input before the escape key was sent
+            p.send_line("three").await?;
+            assert_eq!("three", p.exp_regex("t[a-z]+").await?.1);
+            Ok::<(), Error>(())
+        })
+                .unwrap_or_else(|e| panic!("test_interact_with failed: {}", e));
+    }
+
+    #[test]
+    fn test_other_runtime() {
+        futures::executor::block_on(async {
+            let process = PtyProcess::new(std::process::Command::new("cat"))?;
+            let pty = PolledPty(nonblocking_handle(&process)?);
+            let mut p = AsyncPtySession::with_runtime(process, pty, ThreadTimer, Some(500));
+            p.send_line("hello").await?;
+            assert_eq!("hello", p.read_line().await?);
+            match p.exp_string("never").await {
+                Err(Error(ErrorKind::Timeout(..), _)) => {}
+                r => panic!("expected timeout, got {:?}", r.map_err(|e| e.to_string())),
+            }
+            Ok::<(), Error>(())
+        })
+                .unwrap_or_else(|e| panic!("test_other_runtime failed: {}", e));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_session() {
+        async {
+            let mut p = spawn_async(Command::new("cat"), Some(1000))?;
+            p.send_line("hello world").await?;
+            assert_eq!("hello ", p.exp_string("world").await?);
+            assert_eq!("\r\n", p.exp_regex(r"\s+").await?.1);
+            match p.exp_string("never").await {
+                Err(Error(ErrorKind::Timeout(..), _)) => {}
+                r => panic!("expected timeout, got {:?}", r.map_err(|e| e.to_string())),
+            }
+            Ok::<(), Error>(())
+        }
+                .await
+                .unwrap_or_else(|e| panic!("test_async_session failed: {}", e));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_read_write() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+        async {
+            let process = PtyProcess::new(Command::new("cat"))?;
+            let pty = AsyncPty::new(&process)?;
+            let (reader, mut writer) = tokio::io::split(pty);
+            writer.write_all(b"one\ntwo\n").await.chain_err(|| "cannot write")?;
+            let mut lines = tokio::io::BufReader::new(reader).lines();
+            assert_eq!(Some("one".to_string()), lines.next_line().await.chain_err(|| "read")?);
+            assert_eq!(Some("two".to_string()), lines.next_line().await.chain_err(|| "read")?);
+            Ok::<(), Error>(())
+        }
+                .await
+                .unwrap_or_else(|e| panic!("test_async_read_write failed: {}", e));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_streams() {
+        use futures::StreamExt;
+        async {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "echo one; echo two; printf three"]);
+            let mut p = spawn_async(cmd, Some(1000))?;
+            assert_eq!("one", p.exp_regex(r"\w+").await?.1);
+            let lines: Vec<String> = p.lines().map(|l| l.unwrap()).collect().await;
+            assert_eq!(vec!["", "two", "three"], lines);
+
+            let mut p = spawn_async(Command::new("cat"), Some(1000))?;
+            p.send_line("hello").await?;
+            let chunk = tokio::time::timeout(time::Duration::from_secs(1), p.chunks().next())
+                .await
+                .chain_err(|| "no output")?;
+            assert_eq!(Some(b"hello\r\n".to_vec()), chunk.transpose()?);
+            Ok::<(), Error>(())
+        }
+                .await
+                .unwrap_or_else(|e| panic!("test_streams failed: {}", e));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_concurrent_sessions() {
+        async {
+            let sessions = (0..20).map(|i| async move {
+                let mut cmd = Command::new("bash");
+                cmd.args(["-c", &format!("sleep 0.2; echo done {}", i)]);
+                let mut p = spawn_async(cmd, Some(2000))?;
+                p.exp_eof().await
+            });
+            let start = time::Instant::now();
+            for (i, output) in futures::future::join_all(sessions).await.into_iter().enumerate() {
+                assert_eq!(format!("done {}\r\n", i), output?);
+            }
+            // they ran concurrently, on this one thread
+            assert!(start.elapsed() < time::Duration::from_secs(2));
+            Ok::<(), Error>(())
+        }
+                .await
+                .unwrap_or_else(|e| panic!("test_concurrent_sessions failed: {}", e));
+    }
+}
@@ -0,0 +1,217 @@
+//! Run the same interaction against two programs and compare the results
+//!
+//! Handy for regression checks of command line programs: run the old and the new binary
+//! with the same script and look at the steps whose output differs.
+
+use crate::session::{spawn_command, PtySession};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::process::Command;
+use std::time;
+use crate::errors::*; // load error-chain
+
+/// The outcome of one step of the script, see `Recorder::step`
+#[derive(Debug, Clone)]
+pub struct StepResult {
+    pub name: String,
+    /// what the step returned, or the error message if it failed
+    pub output: std::result::Result<String, String>,
+    pub duration: time::Duration,
+}
+
+/// All steps of the script run against one program
+#[derive(Debug, Clone, Default)]
+pub struct RunResult {
+    pub steps: Vec<StepResult>,
+    /// set if the script failed, either in a step or in between
+    pub error: Option<String>,
+}
+
+/// A step whose output differs between the two runs
+#[derive(Debug, Clone)]
+pub struct StepDiff {
+    pub name: String,
+    /// `None` if the step didn't run at all
+    pub a: Option<StepResult>,
+    pub b: Option<StepResult>,
+}
+
+/// The result of `compare_sessions`
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub a: RunResult,
+    pub b: RunResult,
+}
+
+impl Comparison {
+    /// The steps whose output (or error) differs, in order. Steps are matched by position.
+    pub fn differences(&self) -> Vec<StepDiff> {
+        let len = std::cmp::max(self.a.steps.len(), self.b.steps.len());
+        (0..len)
+            .filter_map(|i| {
+                let a = self.a.steps.get(i);
+                let b = self.b.steps.get(i);
+                match (a, b) {
+                    (Some(a), Some(b)) if a.name == b.name && a.output == b.output => None,
+                    _ => Some(StepDiff {
+                        name: a.or(b).map(|s| s.name.clone()).unwrap_or_default(),
+                        a: a.cloned(),
+                        b: b.cloned(),
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// If both runs produced the same output in every step (timings are not compared)
+    pub fn is_same(&self) -> bool {
+        self.a.error == self.b.error && self.differences().is_empty()
+    }
+}
+
+/// A report listing every step with the timings of both runs and the differing outputs
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let len = std::cmp::max(self.a.steps.len(), self.b.steps.len());
+        for i in 0..len {
+            let a = self.a.steps.get(i);
+            let b = self.b.steps.get(i);
+            let name = a.or(b).map(|s| s.name.as_str()).unwrap_or("");
+            let ms = |s: Option<&StepResult>| {
+                s.map_or("-".to_string(), |s| format!("{} ms", s.duration.as_millis()))
+            };
+            let same = matches!((a, b), (Some(a), Some(b)) if a.output == b.output);
+            writeln!(f, "{} {}: {} / {}", if same { " " } else { "!" }, name, ms(a), ms(b))?;
+            if !same {
+                writeln!(f, "    a: {:?}", a.map(|s| &s.output))?;
+                writeln!(f, "    b: {:?}", b.map(|s| &s.output))?;
+            }
+        }
+        if let Some(ref e) = self.a.error {
+            writeln!(f, "a failed: {}", e)?;
+        }
+        if let Some(ref e) = self.b.error {
+            writeln!(f, "b failed: {}", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Handed to the script of `compare_sessions`, derefs to the session
+pub struct Recorder {
+    session: PtySession,
+    result: RunResult,
+}
+
+impl Recorder {
+    /// Run one named step of the script and record what it returns (usually some output
+    /// of the process) and how long it took. The error of a failing step is recorded and
+    /// returned, so `?` ends the script.
+    pub fn step<F>(&mut self, name: &str, f: F) -> Result<String>
+        where F: FnOnce(&mut PtySession) -> Result<String>
+    {
+        let start = time::Instant::now();
+        let output = f(&mut self.session);
+        self.result.steps.push(StepResult {
+            name: name.to_string(),
+            output: output.as_ref().map(|s| s.clone()).map_err(|e| e.to_string()),
+            duration: start.elapsed(),
+        });
+        output
+    }
+}
+
+impl Deref for Recorder {
+    type Target = PtySession;
+    fn deref(&self) -> &PtySession {
+        &self.session
+    }
+}
+
+impl DerefMut for Recorder {
+    fn deref_mut(&mut self) -> &mut PtySession {
+        &mut self.session
+    }
+}
+
+/// Spawn `a`, run `script` against it, then the same with `b` and compare the steps
+/// recorded with `Recorder::step`. Failures of the script are recorded, not returned,
+/// only failing to spawn is an error.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::compare::compare_sessions;
+/// use std::process::Command;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let mut old = Command::new("bash");
+/// old.args(["-c", "read name; echo \"hello $name\""]);
+/// let mut new = Command::new("bash");
+/// new.args(["-c", "read name; echo \"hi $name\""]);
+/// let comparison = compare_sessions(old, new, Some(1000), |r| {
+///     r.send_line("polly")?;
+///     r.step("greeting", |s| s.read_line())?;
+///     Ok(())
+/// })?;
+/// assert!(!comparison.is_same());
+/// println!("{}", comparison);
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub fn compare_sessions<F>(a: Command, b: Command, timeout_ms: Option<u64>, script: F)
+                           -> Result<Comparison>
+    where F: Fn(&mut Recorder) -> Result<()>
+{
+    let run = |command: Command| -> Result<RunResult> {
+        let session = spawn_command(command, timeout_ms)?;
+        let mut recorder = Recorder { session, result: RunResult::default() };
+        if let Err(e) = script(&mut recorder) {
+            recorder.result.error = Some(e.to_string());
+        }
+        Ok(recorder.result)
+    };
+    Ok(Comparison { a: run(a)?, b: run(b)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(script: &str) -> Command {
+        let mut cmd = Command::new("bash");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[test]
+    fn test_compare_sessions() {
+        || -> Result<()> {
+            let script = |r: &mut Recorder| -> Result<()> {
+                r.step("version", |s| s.read_line())?;
+                r.send_line("2")?;
+                r.step("double", |s| s.read_line())?;
+                r.step("bye", |s| s.exp_string("bye"))?;
+                Ok(())
+            };
+            let same = compare_sessions(cmd("echo v1; read n; echo $((n*2)); echo bye"),
+                                        cmd("echo v1; read n; echo $((n+n)); echo bye"),
+                                        Some(1000), script)?;
+            assert!(same.is_same(), "{}", same);
+
+            let comparison = compare_sessions(cmd("echo v1; read n; echo $((n*2)); echo bye"),
+                                              cmd("echo v2; read n; echo $((n*3))"),
+                                              Some(500), script)?;
+            let diffs: Vec<String> = comparison.differences().into_iter().map(|d| d.name).collect();
+            assert_eq!(vec!["version", "double", "bye"], diffs);
+            assert!(comparison.a.error.is_none());
+            assert!(comparison.b.error.is_some());
+            assert!(comparison.to_string().contains("! version"), "{}", comparison);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_compare_sessions failed: {}", e));
+    }
+}
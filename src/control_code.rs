@@ -0,0 +1,139 @@
+//! Control codes which can be sent to a process, see `PtySession::send_control`
+
+use std::convert::TryFrom;
+use crate::errors::*; // load error-chain
+
+/// The ASCII control codes, i.e. what your terminal sends when you press Ctrl plus a key.
+///
+/// The comment on each variant is the key combination producing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlCode {
+    /// Ctrl-@
+    Null = 0,
+    /// Ctrl-A
+    StartOfHeading = 1,
+    /// Ctrl-B
+    StartOfText = 2,
+    /// Ctrl-C, usually sends SIGINT
+    EndOfText = 3,
+    /// Ctrl-D, usually EOF when typed at the start of a line
+    EndOfTransmission = 4,
+    /// Ctrl-E
+    Enquiry = 5,
+    /// Ctrl-F
+    Acknowledge = 6,
+    /// Ctrl-G
+    Bell = 7,
+    /// Ctrl-H
+    Backspace = 8,
+    /// Ctrl-I
+    HorizontalTab = 9,
+    /// Ctrl-J
+    LineFeed = 10,
+    /// Ctrl-K
+    VerticalTab = 11,
+    /// Ctrl-L, clears the screen in most shells
+    FormFeed = 12,
+    /// Ctrl-M
+    CarriageReturn = 13,
+    /// Ctrl-N
+    ShiftOut = 14,
+    /// Ctrl-O
+    ShiftIn = 15,
+    /// Ctrl-P
+    DataLinkEscape = 16,
+    /// Ctrl-Q, resumes output (XON)
+    DeviceControl1 = 17,
+    /// Ctrl-R
+    DeviceControl2 = 18,
+    /// Ctrl-S, pauses output (XOFF)
+    DeviceControl3 = 19,
+    /// Ctrl-T
+    DeviceControl4 = 20,
+    /// Ctrl-U
+    NegativeAcknowledge = 21,
+    /// Ctrl-V
+    SynchronousIdle = 22,
+    /// Ctrl-W
+    EndOfTransmissionBlock = 23,
+    /// Ctrl-X
+    Cancel = 24,
+    /// Ctrl-Y
+    EndOfMedium = 25,
+    /// Ctrl-Z, usually sends SIGTSTP (suspend)
+    Substitute = 26,
+    /// Ctrl-[, the escape key
+    Escape = 27,
+    /// Ctrl-\, usually sends SIGQUIT
+    FileSeparator = 28,
+    /// Ctrl-]
+    GroupSeparator = 29,
+    /// Ctrl-^
+    RecordSeparator = 30,
+    /// Ctrl-_
+    UnitSeparator = 31,
+}
+
+impl ControlCode {
+    const ALL: [ControlCode; 32] = [
+        ControlCode::Null, ControlCode::StartOfHeading, ControlCode::StartOfText,
+        ControlCode::EndOfText, ControlCode::EndOfTransmission, ControlCode::Enquiry,
+        ControlCode::Acknowledge, ControlCode::Bell, ControlCode::Backspace,
+        ControlCode::HorizontalTab, ControlCode::LineFeed, ControlCode::VerticalTab,
+        ControlCode::FormFeed, ControlCode::CarriageReturn, ControlCode::ShiftOut,
+        ControlCode::ShiftIn, ControlCode::DataLinkEscape, ControlCode::DeviceControl1,
+        ControlCode::DeviceControl2, ControlCode::DeviceControl3, ControlCode::DeviceControl4,
+        ControlCode::NegativeAcknowledge, ControlCode::SynchronousIdle,
+        ControlCode::EndOfTransmissionBlock, ControlCode::Cancel, ControlCode::EndOfMedium,
+        ControlCode::Substitute, ControlCode::Escape, ControlCode::FileSeparator,
+        ControlCode::GroupSeparator, ControlCode::RecordSeparator, ControlCode::UnitSeparator,
+    ];
+}
+
+impl From<ControlCode> for u8 {
+    fn from(code: ControlCode) -> u8 {
+        code as u8
+    }
+}
+
+/// Turn the key pressed together with Ctrl into the control code,
+/// e.g. `'c'` into `ControlCode::EndOfText`. Upper/smaller case does not matter.
+impl TryFrom<char> for ControlCode {
+    type Error = Error;
+
+    fn try_from(c: char) -> Result<ControlCode> {
+        let index = match c {
+            '@' => 0,
+            'a'..='z' => c as u8 + 1 - b'a',
+            'A'..='Z' => c as u8 + 1 - b'A',
+            '[' => 27,
+            '\\' => 28,
+            ']' => 29,
+            '^' => 30,
+            '_' => 31,
+            _ => return Err(format!("I don't understand Ctrl-{}", c).into()),
+        };
+        Ok(ControlCode::ALL[index as usize])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_char() {
+        assert_eq!(ControlCode::EndOfText, ControlCode::try_from('c').unwrap());
+        assert_eq!(ControlCode::EndOfText, ControlCode::try_from('C').unwrap());
+        assert_eq!(ControlCode::Escape, ControlCode::try_from('[').unwrap());
+        assert_eq!(26u8, ControlCode::try_from('z').unwrap().into());
+        assert!(ControlCode::try_from('1').is_err());
+    }
+
+    #[test]
+    fn test_all_in_order() {
+        for (i, code) in ControlCode::ALL.iter().enumerate() {
+            assert_eq!(i as u8, u8::from(*code));
+        }
+    }
+}
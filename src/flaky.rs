@@ -0,0 +1,192 @@
+//! Retry flaky interactions and find out what differed between the attempts
+//!
+//! `flaky_retry` reruns a script against a fresh session as long as it fails with a
+//! timeout, recording the transcript of every attempt. The report shows a line diff of
+//! a failing attempt against a passing one, which usually points at the race.
+
+use crate::session::PtySession;
+use std::fmt;
+use std::time;
+use crate::errors::*; // load error-chain
+
+/// One run of the script, see `FlakyRun::attempts`
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    /// all output of the process, see `PtySession::transcript`
+    pub transcript: String,
+    /// the error message if the attempt failed
+    pub error: Option<String>,
+    pub duration: time::Duration,
+}
+
+/// The result of `flaky_retry`
+pub struct FlakyRun<T> {
+    /// what the last attempt returned
+    pub result: Result<T>,
+    pub attempts: Vec<Attempt>,
+}
+
+impl<T> FlakyRun<T> {
+    /// If an attempt failed before one passed
+    pub fn is_flaky(&self) -> bool {
+        self.result.is_ok() && self.attempts.len() > 1
+    }
+}
+
+/// A report of all attempts and a diff of the transcripts of the first failing attempt
+/// and the passing one (or the last one, if none passed)
+impl<T> fmt::Display for FlakyRun<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            match attempt.error {
+                Some(ref e) => writeln!(f, "attempt {}: failed after {} ms: {}",
+                                        i + 1, attempt.duration.as_millis(), e)?,
+                None => writeln!(f, "attempt {}: passed after {} ms",
+                                 i + 1, attempt.duration.as_millis())?,
+            }
+        }
+        let failed = self.attempts.iter().position(|a| a.error.is_some());
+        let other = self.attempts.iter().rposition(|a| a.error.is_none())
+            .unwrap_or(self.attempts.len().saturating_sub(1));
+        match failed {
+            Some(failed) if failed != other => {
+                writeln!(f, "--- attempt {}", failed + 1)?;
+                writeln!(f, "+++ attempt {}", other + 1)?;
+                for line in diff_lines(&self.attempts[failed].transcript,
+                                       &self.attempts[other].transcript) {
+                    writeln!(f, "{}", line)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// line diff based on the longest common subsequence, lines are prefixed with
+// "  " (in both), "- " (only in a) or "+ " (only in b)
+fn diff_lines(a: &str, b: &str) -> Vec<String> {
+    let a: Vec<&str> = a.lines().collect();
+    let b: Vec<&str> = b.lines().collect();
+    // lcs[i][j]: length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut res = Vec::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            res.push(format!("  {:?}", a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push(format!("- {:?}", a[i]));
+            i += 1;
+        } else {
+            res.push(format!("+ {:?}", b[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+/// Run `script` against a session created by `spawn`, and rerun it against a fresh
+/// session up to `retries` times as long as it fails with a timeout. Other errors are
+/// not retried. Print the returned `FlakyRun` for a report of the attempts.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::flaky::flaky_retry;
+/// use rexpect::spawn;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let run = flaky_retry(2, || spawn("cat", Some(1000)), |s| {
+///     s.send_line("hello")?;
+///     s.read_line()
+/// });
+/// println!("{}", run);
+/// assert_eq!("hello", run.result?);
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub fn flaky_retry<T, S, F>(retries: usize, mut spawn: S, mut script: F) -> FlakyRun<T>
+    where S: FnMut() -> Result<PtySession>,
+          F: FnMut(&mut PtySession) -> Result<T>
+{
+    let mut attempts = Vec::new();
+    loop {
+        let start = time::Instant::now();
+        let mut session = match spawn() {
+            Ok(session) => session,
+            Err(e) => return FlakyRun { result: Err(e), attempts },
+        };
+        session.start_transcript();
+        let result = script(&mut session);
+        attempts.push(Attempt {
+            transcript: session.transcript().unwrap_or_default(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            duration: start.elapsed(),
+        });
+        match result {
+            Err(Error(ErrorKind::Timeout(..), _)) if attempts.len() <= retries => {}
+            result => return FlakyRun { result, attempts },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::spawn_command;
+    use std::process::Command;
+
+    #[test]
+    fn test_flaky_retry() {
+        || -> Result<()> {
+            // fails the first two times
+            let dir = tempfile::tempdir().chain_err(|| "cannot create temp dir")?;
+            let counter = dir.path().join("counter");
+            let script = format!("n=$(cat {0} 2>/dev/null || echo 0); echo $((n+1)) > {0}; \
+                                  echo start; [ $n -ge 2 ] && echo ready; cat",
+                                 counter.display());
+            let run = flaky_retry(3, || {
+                let mut cmd = Command::new("bash");
+                cmd.args(["-c", &script]);
+                spawn_command(cmd, Some(500))
+            }, |s| s.exp_string("ready"));
+            assert!(run.is_flaky(), "{}", run);
+            assert_eq!(3, run.attempts.len());
+            assert!(run.attempts[0].error.is_some());
+            assert_eq!(None, run.attempts[2].error);
+            let report = run.to_string();
+            assert!(report.contains("--- attempt 1\n+++ attempt 3\n  \"start\"\n+ \"ready\""),
+                    "{}", report);
+            run.result?;
+
+            let run = flaky_retry(1, || spawn_command(Command::new("cat"), Some(100)),
+                                  |s| s.exp_string("never"));
+            assert_eq!(2, run.attempts.len());
+            assert!(!run.is_flaky());
+            assert!(run.result.is_err());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_flaky_retry failed: {}", e));
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(vec!["  \"a\"", "- \"b\"", "+ \"x\"", "  \"c\""],
+                   diff_lines("a\nb\nc", "a\nx\nc"));
+    }
+}
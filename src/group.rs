@@ -0,0 +1,251 @@
+//! Start and stop several processes which depend on each other
+//!
+//! A bit like docker-compose for interactive processes: every process declares a
+//! pattern telling that it's ready and the processes it depends on. `start` spawns
+//! them in dependency order, `stop` ends them in reverse order.
+
+use crate::process::SpawnOptions;
+use crate::reader::ReadUntil;
+use crate::session::{spawn_with_options, PtySession};
+use std::fmt;
+use std::process::Command;
+use std::time;
+use crate::errors::*; // load error-chain
+
+/// What happened to a process of a `SessionGroup`, see `SessionGroup::timeline`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupEvent {
+    Spawned,
+    Ready,
+    Stopped,
+}
+
+/// One entry of `SessionGroup::timeline`
+#[derive(Debug, Clone)]
+pub struct TimelineEntry {
+    /// time since `start` was called
+    pub at: time::Duration,
+    /// name of the process
+    pub name: String,
+    pub event: GroupEvent,
+}
+
+impl fmt::Display for TimelineEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>8} ms  {}: {:?}", self.at.as_millis(), self.name, self.event)
+    }
+}
+
+struct Member {
+    name: String,
+    command: Option<Command>,
+    ready: ReadUntil,
+    depends_on: Vec<String>,
+}
+
+/// A group of processes which are started in dependency order
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::group::SessionGroup;
+/// use rexpect::ReadUntil;
+/// use std::process::Command;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let mut server = Command::new("bash");
+/// server.args(["-c", "echo listening; cat"]);
+/// let mut client = Command::new("bash");
+/// client.args(["-c", "echo connected; cat"]);
+///
+/// let mut group = SessionGroup::new(Some(2000));
+/// group.add("server", server, ReadUntil::String("listening".into()), &[]);
+/// group.add("client", client, ReadUntil::String("connected".into()), &["server"]);
+/// group.start()?;
+/// group.session("client").unwrap().send_line("hello")?;
+/// group.stop()?;
+/// for entry in group.timeline() {
+///     println!("{}", entry);
+/// }
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub struct SessionGroup {
+    timeout_ms: Option<u64>,
+    members: Vec<Member>,
+    // running sessions in start order
+    sessions: Vec<(String, PtySession)>,
+    started: Option<time::Instant>,
+    timeline: Vec<TimelineEntry>,
+}
+
+impl SessionGroup {
+    /// Create an empty group, `timeout_ms` is the timeout of every session
+    /// (also used to wait for the ready patterns)
+    pub fn new(timeout_ms: Option<u64>) -> Self {
+        SessionGroup {
+            timeout_ms,
+            members: Vec::new(),
+            sessions: Vec::new(),
+            started: None,
+            timeline: Vec::new(),
+        }
+    }
+
+    /// Declare a process named `name` which is ready as soon as `ready` shows up in its
+    /// output, and which must only be started after all processes in `depends_on` are ready
+    pub fn add(&mut self, name: &str, command: Command, ready: ReadUntil, depends_on: &[&str]) {
+        self.members.push(Member {
+            name: name.to_string(),
+            command: Some(command),
+            ready,
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+        });
+    }
+
+    /// Start all processes in dependency order, waiting for each to be ready before
+    /// starting the ones depending on it.
+    ///
+    /// Fails if a dependency is unknown or cyclic, or if a process doesn't get ready.
+    /// Processes started before the failure keep running until `stop` is called or the
+    /// group is dropped.
+    pub fn start(&mut self) -> Result<()> {
+        let order = self.start_order()?;
+        self.started = Some(time::Instant::now());
+        for index in order {
+            let name = self.members[index].name.clone();
+            let command = match self.members[index].command.take() {
+                Some(command) => command,
+                None => return Err(format!("{} was already started", name).into()),
+            };
+            let mut session = spawn_with_options(command, self.timeout_ms, SpawnOptions::default())
+                .chain_err(|| format!("cannot start {}", name))?;
+            self.record(&name, GroupEvent::Spawned);
+            session.exp_any(vec![self.members[index].ready.clone()])
+                .chain_err(|| format!("{} didn't get ready", name))?;
+            self.record(&name, GroupEvent::Ready);
+            self.sessions.push((name, session));
+        }
+        Ok(())
+    }
+
+    /// Stop all running processes in reverse start order
+    pub fn stop(&mut self) -> Result<()> {
+        while let Some((name, mut session)) = self.sessions.pop() {
+            session.process.exit().chain_err(|| format!("cannot stop {}", name))?;
+            self.record(&name, GroupEvent::Stopped);
+        }
+        Ok(())
+    }
+
+    /// The session of the running process `name`
+    pub fn session(&mut self, name: &str) -> Option<&mut PtySession> {
+        self.sessions
+            .iter_mut()
+            .find(|(n, _)| n == name)
+            .map(|(_, session)| session)
+    }
+
+    /// Everything which happened since `start`, in order
+    pub fn timeline(&self) -> &[TimelineEntry] {
+        &self.timeline
+    }
+
+    fn record(&mut self, name: &str, event: GroupEvent) {
+        let at = self.started.map(|s| s.elapsed()).unwrap_or_default();
+        self.timeline.push(TimelineEntry { at, name: name.to_string(), event });
+    }
+
+    // topological sort of the members, keeping the order of `add` where possible
+    fn start_order(&self) -> Result<Vec<usize>> {
+        for member in &self.members {
+            for dep in &member.depends_on {
+                if !self.members.iter().any(|m| &m.name == dep) {
+                    return Err(format!("{} depends on unknown {}", member.name, dep).into());
+                }
+            }
+        }
+        let mut order: Vec<usize> = Vec::new();
+        while order.len() < self.members.len() {
+            let next = (0..self.members.len()).find(|&i| {
+                !order.contains(&i) &&
+                self.members[i].depends_on.iter().all(|dep| {
+                    order.iter().any(|&o| &self.members[o].name == dep)
+                })
+            });
+            match next {
+                Some(i) => order.push(i),
+                None => {
+                    let cyclic: Vec<&str> = (0..self.members.len())
+                        .filter(|i| !order.contains(i))
+                        .map(|i| self.members[i].name.as_str())
+                        .collect();
+                    return Err(format!("cyclic dependencies between {}", cyclic.join(", "))
+                        .into());
+                }
+            }
+        }
+        Ok(order)
+    }
+}
+
+impl Drop for SessionGroup {
+    /// stop in reverse order, the sessions would be dropped in start order otherwise
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(script: &str) -> Command {
+        let mut cmd = Command::new("bash");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    #[test]
+    fn test_start_order() {
+        || -> Result<()> {
+            let mut group = SessionGroup::new(Some(2000));
+            group.add("app", cmd("echo app up; cat"), ReadUntil::String("app up".into()),
+                      &["db", "cache"]);
+            group.add("db", cmd("sleep 0.2; echo db up; cat"), ReadUntil::String("db up".into()),
+                      &[]);
+            group.add("cache", cmd("echo cache up; cat"), ReadUntil::String("cache up".into()),
+                      &["db"]);
+            group.start()?;
+            group.session("app").expect("app is running").send_line("ping")?;
+            group.session("app").unwrap().exp_string("ping")?;
+            group.stop()?;
+
+            let events: Vec<(&str, GroupEvent)> = group.timeline()
+                .iter()
+                .map(|e| (e.name.as_str(), e.event))
+                .collect();
+            assert_eq!(vec![("db", GroupEvent::Spawned), ("db", GroupEvent::Ready),
+                            ("cache", GroupEvent::Spawned), ("cache", GroupEvent::Ready),
+                            ("app", GroupEvent::Spawned), ("app", GroupEvent::Ready),
+                            ("app", GroupEvent::Stopped), ("cache", GroupEvent::Stopped),
+                            ("db", GroupEvent::Stopped)],
+                       events);
+            assert!(group.timeline()[1].at >= time::Duration::from_millis(200));
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_start_order failed: {}", e));
+    }
+
+    #[test]
+    fn test_cyclic_dependencies() {
+        let mut group = SessionGroup::new(Some(1000));
+        group.add("a", cmd("cat"), ReadUntil::EOF, &["b"]);
+        group.add("b", cmd("cat"), ReadUntil::EOF, &["a"]);
+        assert!(group.start().is_err());
+        assert!(group.timeline().is_empty());
+    }
+}
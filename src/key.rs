@@ -0,0 +1,106 @@
+//! Keys which can be sent to a process, see `PtySession::send_key`
+
+use crate::control_code::ControlCode;
+
+/// A key press, encoded the way an xterm compatible terminal sends it
+/// (cursor keys in normal mode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// a printable char, sent UTF-8 encoded
+    Char(char),
+    /// Ctrl plus a key, see `ControlCode`
+    Ctrl(ControlCode),
+    /// the return key, sends `\r` (the terminal translates it into `\n` for the program)
+    Enter,
+    Tab,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+}
+
+impl Key {
+    /// The bytes the terminal sends for this key. `buf` is only used for `Key::Char` and
+    /// `Key::Ctrl`, so encoding never allocates.
+    pub fn encode(self, buf: &mut [u8; 4]) -> &[u8] {
+        match self {
+            Key::Char(c) => c.encode_utf8(buf).as_bytes(),
+            Key::Ctrl(code) => {
+                buf[0] = code.into();
+                &buf[..1]
+            }
+            Key::Enter => b"\r",
+            Key::Tab => b"\t",
+            Key::Backspace => b"\x7f",
+            Key::Escape => b"\x1b",
+            Key::Up => b"\x1b[A",
+            Key::Down => b"\x1b[B",
+            Key::Right => b"\x1b[C",
+            Key::Left => b"\x1b[D",
+            Key::Home => b"\x1b[H",
+            Key::End => b"\x1b[F",
+            Key::Insert => b"\x1b[2~",
+            Key::Delete => b"\x1b[3~",
+            Key::PageUp => b"\x1b[5~",
+            Key::PageDown => b"\x1b[6~",
+            Key::F1 => b"\x1bOP",
+            Key::F2 => b"\x1bOQ",
+            Key::F3 => b"\x1bOR",
+            Key::F4 => b"\x1bOS",
+            Key::F5 => b"\x1b[15~",
+            Key::F6 => b"\x1b[17~",
+            Key::F7 => b"\x1b[18~",
+            Key::F8 => b"\x1b[19~",
+            Key::F9 => b"\x1b[20~",
+            Key::F10 => b"\x1b[21~",
+            Key::F11 => b"\x1b[23~",
+            Key::F12 => b"\x1b[24~",
+        }
+    }
+}
+
+impl From<char> for Key {
+    fn from(c: char) -> Key {
+        Key::Char(c)
+    }
+}
+
+impl From<ControlCode> for Key {
+    fn from(code: ControlCode) -> Key {
+        Key::Ctrl(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        let mut buf = [0u8; 4];
+        assert_eq!(b"\x1b[A", Key::Up.encode(&mut buf));
+        assert_eq!("ä".as_bytes(), Key::Char('ä').encode(&mut buf));
+        assert_eq!(&[3], Key::Ctrl(ControlCode::EndOfText).encode(&mut buf));
+        assert_eq!(b"\x1b[24~", Key::F12.encode(&mut buf));
+    }
+}
@@ -12,6 +12,16 @@
 //!   strings/regex/...
 //! - [process](process/index.html): spawn a process in a pty
 //!
+//! # Features
+//!
+//! - `single-threaded`: the sessions read the output on your thread while you wait for it
+//!   instead of spawning a reader thread, see `NBReader::new_inline`. Replays of snapshots
+//!   (`Snapshot::replay`) read the recording up front. Only `tail_file` still spawns a
+//!   thread. Single sessions can do the same with `SpawnOptions::read_inline`.
+//! - `async`: [async_session](async_session/index.html), sessions with `async fn`s for
+//!   tokio, without any threads per session.
+//! - `async-core`: the same without tokio, for other runtimes.
+//!
 //! # Basic example
 //!
 //! ```no_run
@@ -78,12 +88,26 @@
 //!
 //! ```
 
+#[macro_use]
+mod macros;
 pub mod process;
 pub mod session;
 pub mod reader;
+pub mod control_code;
+pub mod key;
+pub mod network_cli;
+pub mod group;
+pub mod ansi;
+pub mod compare;
+pub mod flaky;
+pub mod scope;
+#[cfg(feature = "async-core")]
+pub mod async_session;
 
-pub use session::{spawn, spawn_bash, spawn_python};
+pub use session::{spawn, spawn_bash, spawn_python, spawn_with_options};
 pub use reader::ReadUntil;
+pub use control_code::ControlCode;
+pub use key::Key;
 
 pub mod errors {
     use std::time;
@@ -95,7 +119,7 @@ pub mod errors {
                 description("End of filestream (usually stdout) occurred, most probably\
                              because the process terminated")
                 display("EOF (End of File): Expected {} but got EOF after reading \"{}\", \
-                             process terminated with {:?}", expected, got,
+                             process terminated with {:?}", expected, got.escape_debug(),
                              exit_code.map(|status| format!("{:?}", status))
                              .unwrap_or("unknown".to_string()))
             }
@@ -108,8 +132,25 @@ pub mod errors {
                 description("The process didn't end within the given timeout")
                 display("Timeout Error: Expected {} but got \"{}\" (after waiting {} ms)",
                         expected, got, (timeout.as_secs() * 1000) as u32
-                        + timeout.subsec_nanos() / 1_000_000)
+                        + timeout.subsec_millis())
+            }
+            Cancelled(expected:String, got:String) {
+                description("Waiting for the output was cancelled with a CancelHandle")
+                display("Cancelled while expecting {}, got \"{}\"", expected, got.escape_debug())
+            }
+            FailPattern(expected:String, pattern:String, got:String) {
+                description("A fail pattern showed up in the output before the expected \
+                             pattern did")
+                display("Fail pattern {} seen while expecting {}, got \"{}\"",
+                        pattern, expected, got.escape_debug())
             }
         }
     }
+
+    // lets `send_control` take anything which converts into a `ControlCode`
+    impl From<std::convert::Infallible> for Error {
+        fn from(never: std::convert::Infallible) -> Error {
+            match never {}
+        }
+    }
 }
@@ -0,0 +1,80 @@
+//! `expect!` and `send!`, shortcuts for the session calls which add the call site to errors
+
+/// Wait for output of a session: `expect!(p, "text")` calls `exp_string`,
+/// `expect!(p, regex "...")` calls `exp_regex` and `expect!(p, eof)` calls `exp_eof`.
+///
+/// Errors start with the file and line of the macro call, so a timeout in a long script
+/// points at the step which failed. The error of the session call is the cause.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::{expect, send, spawn};
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let mut p = spawn("cat", Some(1000))?;
+/// send!(p, "hello {}", "world")?;
+/// expect!(p, "hello")?;
+/// expect!(p, regex r"w\w+")?;
+/// p.send_eof()?;
+/// expect!(p, eof)?;
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! expect {
+    ($session:expr, eof) => {
+        $crate::__at_call_site!($session.exp_eof())
+    };
+    ($session:expr, regex $regex:expr) => {
+        $crate::__at_call_site!($session.exp_regex($regex))
+    };
+    ($session:expr, $needle:expr) => {
+        $crate::__at_call_site!($session.exp_string($needle))
+    };
+}
+
+/// Send a line to a session, formatted like `format!`, see `expect!`
+#[macro_export]
+macro_rules! send {
+    ($session:expr, $($format:tt)+) => {
+        $crate::__at_call_site!($session.send_line(&format!($($format)+)))
+    };
+}
+
+// `file!` and `line!` within a macro are the ones of the outermost macro call
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __at_call_site {
+    ($call:expr) => {
+        $call.map_err(|e| {
+            let msg = format!("{}:{}: {}", file!(), line!(), e);
+            $crate::errors::Error::with_chain(e, $crate::errors::ErrorKind::Msg(msg))
+        })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::*;
+    use crate::spawn;
+
+    #[test]
+    fn test_call_site() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(100))?;
+            send!(p, "{}", 42)?;
+            expect!(p, "42")?;
+            let (line, e) = (line!(), expect!(p, regex "4[0-9]").unwrap_err());
+            let msg = e.to_string();
+            assert!(msg.starts_with(&format!("{}:{}: Timeout Error", file!(), line)), "{}", msg);
+            let cause = e.iter().nth(1).map(|cause| cause.to_string()).unwrap_or_default();
+            assert!(cause.starts_with("Timeout Error"), "{}", cause);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_call_site failed: {}", e));
+    }
+}
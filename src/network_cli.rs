@@ -0,0 +1,253 @@
+//! Preset for the CLIs of network devices (Cisco IOS, Juniper, and alike)
+//!
+//! Handles the login sequence, `--More--` paging, enable mode and the prompt changes
+//! of the configuration mode. The transport is up to you: spawn `ssh`, `telnet` or a
+//! serial console program like `picocom`.
+
+use crate::reader::{ReadUntil, Regex};
+use crate::session::{spawn_command, strip_echo, PtySession};
+use std::ops::{Deref, DerefMut};
+use std::process::Command;
+use crate::errors::*; // load error-chain
+
+/// The mode the device CLI is in, derived from the last seen prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CliMode {
+    /// `router>`
+    User,
+    /// `router#`, after `enable`
+    Privileged,
+    /// `router(config)#`, `router(config-if)#`, ... contains what is within the parens
+    Config(String),
+}
+
+/// A session with a network device CLI, see `spawn_network_cli`
+#[derive(Debug)]
+pub struct NetworkCliSession {
+    /// matches the prompt at the end of the output, e.g. `router>` or `router(config-if)#`
+    pub prompt: Regex,
+
+    /// the underlying session (transport), e.g. ssh or telnet
+    pub pty_session: PtySession,
+
+    /// set this to true if the device echoes the sent commands (which most devices do
+    /// over ssh or telnet), the echo is then stripped from the output of `execute`
+    pub echo_on: bool,
+
+    hostname: String,
+    mode: CliMode,
+}
+
+impl NetworkCliSession {
+    /// Answer the login sequence (`Username:`/`login:` and `Password:` prompts, whichever
+    /// show up) until the first CLI prompt appears
+    pub fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        let needles = vec![ReadUntil::Regex(Regex::new(r"(?i)(username|login): ?$").unwrap()),
+                           ReadUntil::Regex(Regex::new(r"(?i)password: ?$").unwrap()),
+                           ReadUntil::Regex(self.prompt.clone())];
+        loop {
+            let (_, matched) = self.pty_session.exp_any(needles.clone())?;
+            if is_match(&needles[0], &matched) {
+                self.pty_session.send_line(username)?;
+            } else if is_match(&needles[1], &matched) {
+                self.pty_session.send_secret(password)?;
+            } else {
+                self.update_prompt(&matched);
+                return Ok(());
+            }
+        }
+    }
+
+    /// Wait until the prompt shows up and return the output before it
+    pub fn wait_for_prompt(&mut self) -> Result<String> {
+        let (output, prompt) = self.pty_session.exp_regex(self.prompt.as_str())?;
+        self.update_prompt(&prompt);
+        Ok(output)
+    }
+
+    /// Run `cmd` and return its output (without the echoed command and the prompt).
+    /// `--More--` pages are skipped automatically.
+    pub fn execute(&mut self, cmd: &str) -> Result<String> {
+        self.pty_session.send_line(cmd)?;
+        let output = self.wait_for_prompt()?;
+        let output = output.trim_start_matches(['\r', '\n']);
+        let output = if self.echo_on { strip_echo(output, cmd) } else { output };
+        Ok(output.to_string())
+    }
+
+    /// Enter the privileged mode with `enable`, answering the password prompt with `secret`
+    pub fn enable(&mut self, secret: &str) -> Result<()> {
+        self.pty_session.send_line("enable")?;
+        let password = ReadUntil::Regex(Regex::new(r"(?i)password: ?$").unwrap());
+        let (_, matched) = self.pty_session
+            .exp_any(vec![password.clone(), ReadUntil::Regex(self.prompt.clone())])?;
+        if is_match(&password, &matched) {
+            self.pty_session.send_secret(secret)?;
+            self.wait_for_prompt()?;
+        } else {
+            self.update_prompt(&matched);
+        }
+        match self.mode {
+            CliMode::Privileged => Ok(()),
+            _ => Err("enable failed: device is not in privileged mode".into()),
+        }
+    }
+
+    /// Enter the configuration mode with `configure terminal`
+    pub fn configure(&mut self) -> Result<()> {
+        self.execute("configure terminal")?;
+        match self.mode {
+            CliMode::Config(_) => Ok(()),
+            _ => Err("configure failed: device is not in configuration mode".into()),
+        }
+    }
+
+    /// Leave the configuration mode with `end`
+    pub fn end(&mut self) -> Result<()> {
+        self.execute("end")?;
+        Ok(())
+    }
+
+    /// The mode the device was in at the last prompt
+    pub fn mode(&self) -> &CliMode {
+        &self.mode
+    }
+
+    /// The hostname shown in the last prompt
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    fn update_prompt(&mut self, prompt: &str) {
+        if let Some(caps) = self.prompt.captures(prompt) {
+            if let Some(hostname) = caps.name("hostname") {
+                self.hostname = hostname.as_str().to_string();
+            }
+            self.mode = match (caps.name("config"), caps.name("level").map(|m| m.as_str())) {
+                (Some(config), _) => CliMode::Config(config.as_str().to_string()),
+                (None, Some("#")) => CliMode::Privileged,
+                _ => CliMode::User,
+            };
+        }
+    }
+}
+
+// if `s` (which was returned by the reader) is what `needle` found
+fn is_match(needle: &ReadUntil, s: &str) -> bool {
+    crate::reader::find(needle, s, false).is_some()
+}
+
+// make PtySession's methods available directly
+impl Deref for NetworkCliSession {
+    type Target = PtySession;
+    fn deref(&self) -> &PtySession {
+        &self.pty_session
+    }
+}
+
+impl DerefMut for NetworkCliSession {
+    fn deref_mut(&mut self) -> &mut PtySession {
+        &mut self.pty_session
+    }
+}
+
+/// Spawn the transport to a network device, e.g. `ssh admin@router`, and wrap it into
+/// a `NetworkCliSession`. Call `login` next (also with ssh, which asks for the password).
+///
+/// `--More--` pagination prompts are answered with a space during every `exp_*` call.
+///
+/// # Example:
+///
+/// ```no_run
+/// use rexpect::network_cli::spawn_network_cli;
+/// use std::process::Command;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let mut cmd = Command::new("ssh");
+/// cmd.arg("admin@192.168.0.1");
+/// let mut router = spawn_network_cli(cmd, Some(10_000))?;
+/// router.login("admin", "secret")?;
+/// router.enable("enable-secret")?;
+/// let config = router.execute("show running-config")?;
+/// router.configure()?;
+/// router.execute("hostname core1")?;
+/// router.end()?;
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub fn spawn_network_cli(command: Command, timeout: Option<u64>) -> Result<NetworkCliSession> {
+    let mut pty_session = spawn_command(command, timeout)?;
+    pty_session.expect_before(ReadUntil::Regex(Regex::new(r"-+ ?\(?[Mm]ore\)? ?-+").unwrap()),
+                              |s| {
+        s.send(" ")?;
+        s.flush()
+    });
+    Ok(NetworkCliSession {
+        prompt: Regex::new(r"(?:^|\n)(?P<hostname>[\w.@-]+)(?:\((?P<config>[\w.-]+)\))?(?P<level>[>#]) ?$")
+            .unwrap(),
+        pty_session,
+        echo_on: true,
+        hostname: String::new(),
+        mode: CliMode::User,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a (very) simplified IOS
+    const FAKE_ROUTER: &str = r#"
+        printf 'Username: '; read user
+        printf 'Password: '; read pass; echo "$pass"
+        prompt='>'; cfg=''
+        while true; do
+            printf '\nrouter%s%s' "$cfg" "$prompt"
+            read cmd
+            echo "$cmd"
+            case "$cmd" in
+                enable) printf 'Password: '; read secret; echo "$secret"; prompt='#';;
+                'show version') echo 'Version 1.0'; echo 'page 1'; printf ' --More-- ';
+                                read -n1 key; echo; echo 'page 2';;
+                'configure terminal') cfg='(config)';;
+                'interface eth0') cfg='(config-if)';;
+                end) cfg='';;
+            esac
+        done
+    "#;
+
+    #[test]
+    fn test_network_cli() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", FAKE_ROUTER]);
+            let mut router = spawn_network_cli(cmd, Some(2000))?;
+            router.pty_session.start_transcript();
+            router.login("admin", "secret")?;
+            assert_eq!("router", router.hostname());
+            assert_eq!(&CliMode::User, router.mode());
+
+            router.enable("enable-secret")?;
+            assert_eq!(&CliMode::Privileged, router.mode());
+            // the (careless) device echoes the passwords
+            let transcript = router.pty_session.transcript().unwrap();
+            assert!(transcript.contains("********"), "got {:?}", transcript);
+            assert!(!transcript.contains("secret"), "got {:?}", transcript);
+
+            let version = router.execute("show version")?;
+            assert!(version.starts_with("Version 1.0"), "got {:?}", version);
+            assert!(version.contains("page 2"), "got {:?}", version);
+
+            router.configure()?;
+            router.execute("interface eth0")?;
+            assert_eq!(&CliMode::Config("config-if".to_string()), router.mode());
+            router.end()?;
+            assert_eq!(&CliMode::Privileged, router.mode());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_network_cli failed: {}", e));
+    }
+}
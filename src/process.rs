@@ -2,16 +2,19 @@
 
 use std;
 use std::fs::File;
-use std::process::Command;
-use std::os::unix::process::CommandExt;
-use std::os::unix::io::{FromRawFd, AsRawFd};
-use std::{thread, time};
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::os::unix::io::{FromRawFd, AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::{fmt, io, thread, time};
 use nix::pty::{posix_openpt, grantpt, unlockpt, PtyMaster};
-use nix::fcntl::{OFlag, open};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag, open};
 use nix;
 use nix::sys::{stat, termios};
-use nix::unistd::{fork, ForkResult, setsid, dup, dup2, Pid};
-use nix::libc::{STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO};
+use nix::errno::Errno;
+use nix::unistd::{fork, ForkResult, setsid, setpgid, dup2, close, tcgetpgrp, Pid};
+use nix::libc::{EIO, STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO};
 pub use nix::sys::{wait, signal};
 use crate::errors::*; // load error-chain
 
@@ -37,7 +40,7 @@ use crate::errors::*; // load error-chain
 /// use std::process::Command;
 /// use std::fs::File;
 /// use std::io::{BufReader, LineWriter};
-/// use std::os::unix::io::{FromRawFd, AsRawFd};
+/// use std::os::unix::io::{FromRawFd, AsRawFd, RawFd};
 /// use nix::unistd::dup;
 ///
 /// # fn main() {
@@ -59,12 +62,137 @@ pub struct PtyProcess {
     pub pty: PtyMaster,
     pub child_pid: Pid,
     kill_timeout: Option<time::Duration>,
+    termination: Termination,
+    // see `SpawnOptions::kill_process_group`
+    kill_group: bool,
+    // see `SpawnOptions::keep_slave_open`
+    slave: Option<File>,
+    // reaps the child, shared with `wait_handle`s
+    waiter: WaitHandle,
 }
 
 
 #[cfg(target_os = "linux")]
 use nix::pty::ptsname_r;
 
+/// How many processes started with `SpawnOptions::forward_signals` get the signals at most
+pub const MAX_FORWARD_TARGETS: usize = forwarding::MAX_TARGETS;
+
+/// How long dropping a `PtyProcess` without a kill timeout waits for the process to exit
+/// before it sends SIGKILL
+pub const DROP_KILL_TIMEOUT: time::Duration = time::Duration::from_secs(3);
+
+// how long dropping waits for a SIGKILLed process, e.g. one stuck in uninterruptible
+// sleep is left behind after that
+const DROP_REAP_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+// get/set the terminal size, see tty_ioctl(4)
+mod winsize {
+    use nix::{ioctl_read_bad, ioctl_write_ptr_bad};
+    use nix::libc::{winsize, TIOCGWINSZ, TIOCSWINSZ};
+    ioctl_read_bad!(get, TIOCGWINSZ, winsize);
+    ioctl_write_ptr_bad!(set, TIOCSWINSZ, winsize);
+}
+
+// make a terminal the controlling terminal of our session, see tty_ioctl(4)
+mod ctty {
+    use nix::ioctl_write_int_bad;
+    use nix::libc::TIOCSCTTY;
+    ioctl_write_int_bad!(set, TIOCSCTTY);
+}
+
+// forwarding of SIGINT, SIGTERM and SIGHUP, see `SpawnOptions::forward_signals`
+mod forwarding {
+    use nix::libc::{self, c_int, c_void, siginfo_t};
+    use std::mem;
+    use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
+    use std::sync::Once;
+
+    const SIGNALS: [c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+    /// How many processes signals are forwarded to at most
+    pub const MAX_TARGETS: usize = 64;
+
+    // fixed size, so that the handler neither locks nor allocates
+    #[allow(clippy::declare_interior_mutable_const)]
+    const FREE: AtomicI32 = AtomicI32::new(0);
+    // pids to forward to, negative for process groups
+    static TARGETS: [AtomicI32; MAX_TARGETS] = [FREE; MAX_TARGETS];
+
+    // the handlers which were installed before ours, called after forwarding
+    #[allow(clippy::declare_interior_mutable_const)]
+    const DEFAULT: AtomicUsize = AtomicUsize::new(libc::SIG_DFL);
+    static PREVIOUS: [AtomicUsize; 3] = [DEFAULT; 3];
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NO_SIGINFO: AtomicBool = AtomicBool::new(false);
+    static PREVIOUS_SIGINFO: [AtomicBool; 3] = [NO_SIGINFO; 3];
+
+    static INSTALL: Once = Once::new();
+
+    /// Forward the signals to `target` (a pid, or a process group if negative) from now
+    /// on, returns the slot to `unregister` or `None` if all are taken
+    pub fn register(target: i32) -> Option<usize> {
+        INSTALL.call_once(install);
+        TARGETS.iter().position(|slot| {
+            slot.compare_exchange(0, target, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+        })
+    }
+
+    pub fn unregister(slot: usize, target: i32) {
+        let _ = TARGETS[slot].compare_exchange(target, 0, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    fn install() {
+        for (i, &sig) in SIGNALS.iter().enumerate() {
+            unsafe {
+                let mut previous: libc::sigaction = mem::zeroed();
+                if libc::sigaction(sig, std::ptr::null(), &mut previous) != 0 {
+                    continue;
+                }
+                PREVIOUS[i].store(previous.sa_sigaction, Ordering::SeqCst);
+                PREVIOUS_SIGINFO[i].store(previous.sa_flags & libc::SA_SIGINFO != 0,
+                                          Ordering::SeqCst);
+                let mut action: libc::sigaction = mem::zeroed();
+                action.sa_sigaction = forward as *const () as usize;
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(sig, &action, std::ptr::null_mut());
+            }
+        }
+    }
+
+    extern "C" fn forward(sig: c_int, info: *mut siginfo_t, context: *mut c_void) {
+        for slot in TARGETS.iter() {
+            let target = slot.load(Ordering::SeqCst);
+            if target != 0 {
+                unsafe { libc::kill(target, sig) };
+            }
+        }
+        let i = match SIGNALS.iter().position(|&s| s == sig) {
+            Some(i) => i,
+            None => return,
+        };
+        match PREVIOUS[i].load(Ordering::SeqCst) {
+            libc::SIG_IGN => {}
+            libc::SIG_DFL => unsafe {
+                // end the way we would have without forwarding: the signal is blocked
+                // while we handle it, so it's delivered once we return
+                libc::signal(sig, libc::SIG_DFL);
+                libc::raise(sig);
+            },
+            handler if PREVIOUS_SIGINFO[i].load(Ordering::SeqCst) => unsafe {
+                let handler: extern "C" fn(c_int, *mut siginfo_t, *mut c_void) =
+                    mem::transmute(handler);
+                handler(sig, info, context)
+            },
+            handler => unsafe {
+                let handler: extern "C" fn(c_int) = mem::transmute(handler);
+                handler(sig)
+            },
+        }
+    }
+}
+
 #[cfg(target_os = "macos")]
 /// ptsname_r is a linux extension but ptsname isn't thread-safe
 /// instead of using a static mutex this calls ioctl with TIOCPTYGNAME directly
@@ -75,7 +203,7 @@ fn ptsname_r(fd: &PtyMaster) -> nix::Result<String> {
     use nix::libc::{ioctl, TIOCPTYGNAME};
 
     // the buffer size on OSX is 128, defined by sys/ttycom.h
-    let mut buf: [i8; 128] = [0; 128];
+    let mut buf: [nix::libc::c_char; 128] = [0; 128];
 
     unsafe {
         match ioctl(fd.as_raw_fd(), TIOCPTYGNAME as u64, &mut buf) {
@@ -88,71 +216,946 @@ fn ptsname_r(fd: &PtyMaster) -> nix::Result<String> {
     }
 }
 
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+/// the BSDs have no ptsname_r and ptsname isn't thread-safe, so calls are serialized
+fn ptsname_r(fd: &PtyMaster) -> nix::Result<String> {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    unsafe { nix::pty::ptsname(fd) }
+}
+
+/// The kind of pseudo terminal a process runs in, see `PtyProcess::backend`
+///
+/// Lets portable test code ask for capabilities instead of guessing from the platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BackendKind {
+    /// a pty pair opened with `posix_openpt`
+    UnixPty,
+}
+
+impl BackendKind {
+    /// If the terminal can be resized, see `PtyProcess::resize`
+    pub fn supports_resize(self) -> bool {
+        match self {
+            BackendKind::UnixPty => true,
+        }
+    }
+
+    /// If signals can be sent to the process, see `PtyProcess::signal`
+    pub fn supports_signals(self) -> bool {
+        match self {
+            BackendKind::UnixPty => true,
+        }
+    }
+
+    /// If stderr is read separately from stdout. On a pty both end up in the same output.
+    pub fn separate_stderr(self) -> bool {
+        match self {
+            BackendKind::UnixPty => false,
+        }
+    }
+}
+
+/// How `exit` (and dropping) ends the process, see `PtyProcess::set_termination`. Dropping
+/// sends SIGKILL after `DROP_KILL_TIMEOUT` if there's no kill timeout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Termination {
+    /// send SIGTERM every 100ms until the process is gone and SIGKILL once the kill
+    /// timeout passed (the default)
+    #[default]
+    Repeat,
+    /// send `signal` once and give the process `grace` to exit by itself before it gets
+    /// SIGKILL, e.g. so that a program built with coverage instrumentation can write
+    /// its profile data
+    Graceful { signal: signal::Signal, grace: time::Duration },
+}
+
+/// Options for starting a process in a pty, see `PtyProcess::new_with_options`
+///
+/// # Example
+///
+/// ```
+/// use rexpect::process::{PtyProcess, RetryPolicy, SpawnOptions};
+/// use std::process::Command;
+///
+/// let options = SpawnOptions::new().retry(RetryPolicy::new(3));
+/// let process = PtyProcess::new_with_options(Command::new("cat"), options);
+/// ```
+///
+/// # Nested sessions
+///
+/// The process may use rexpect (or any other pty code) itself, e.g. when testing a
+/// terminal multiplexer. All file descriptors of the pty are opened close-on-exec, so
+/// neither the process nor the processes of other sessions inherit them, and a session
+/// sees EOF as soon as its own process exits (unless `keep_slave_open` is set). The
+/// process is the leader of a new session
+/// and by default the pty is its controlling terminal, see `new_session` and
+/// `controlling_tty`.
+#[derive(Clone)]
+pub struct SpawnOptions {
+    retry: RetryPolicy,
+    new_session: bool,
+    controlling_tty: bool,
+    kill_process_group: bool,
+    start_suspended: bool,
+    keep_slave_open: bool,
+    forward_signals: bool,
+    read_inline: bool,
+    window_size: Option<(u16, u16)>,
+    terminal_mode: TerminalMode,
+    rlimits: Vec<(Resource, u64, u64)>,
+    priority: Option<i32>,
+    #[cfg(target_os = "linux")]
+    cpu_affinity: Option<Vec<usize>>,
+    #[cfg(target_os = "linux")]
+    namespaces: Vec<Namespace>,
+    #[cfg(target_os = "linux")]
+    cgroup: Option<PathBuf>,
+    on_spawn: Option<SpawnHook>,
+    on_exit: Option<ExitHook>,
+}
+
+/// Terminal settings (termios) of the pty, see `SpawnOptions::terminal_mode`. Everything
+/// not set stays at what the system gives a new pty, except that echo is always off.
+///
+/// # Example
+///
+/// ```
+/// use rexpect::process::{PtyProcess, SpawnOptions, TerminalMode};
+/// use std::process::Command;
+///
+/// // a program which reads key by key, Ctrl-C is sent to it as a char
+/// let mode = TerminalMode::new().canonical(false).signals(false).min_time(1, 0);
+/// let options = SpawnOptions::new().terminal_mode(mode);
+/// let process = PtyProcess::new_with_options(Command::new("cat"), options);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TerminalMode {
+    raw: bool,
+    canonical: Option<bool>,
+    signals: Option<bool>,
+    flow_control: Option<bool>,
+    min_time: Option<(u8, u8)>,
+}
+
+impl TerminalMode {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raw mode like `cfmakeraw(3)`: no line editing, no signals from control chars and no
+    /// translation of input or output (the output has `\n` instead of `\r\n`). The other
+    /// settings are applied on top.
+    pub fn raw(mut self, raw: bool) -> Self {
+        self.raw = raw;
+        self
+    }
+
+    /// Line editing (`ICANON`): the program gets input line by line, after Enter
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.canonical = Some(canonical);
+        self
+    }
+
+    /// Turn control chars like Ctrl-C into signals (`ISIG`)
+    pub fn signals(mut self, signals: bool) -> Self {
+        self.signals = Some(signals);
+        self
+    }
+
+    /// Stop and restart output with Ctrl-S/Ctrl-Q (`IXON` and `IXOFF`)
+    pub fn flow_control(mut self, flow_control: bool) -> Self {
+        self.flow_control = Some(flow_control);
+        self
+    }
+
+    /// Without line editing, a read returns once `min` bytes are there or `time` tenths of
+    /// a second passed since the last byte (`VMIN` and `VTIME`)
+    pub fn min_time(mut self, min: u8, time: u8) -> Self {
+        self.min_time = Some((min, time));
+        self
+    }
+
+    fn apply(&self, t: &mut termios::Termios) {
+        if self.raw {
+            termios::cfmakeraw(t);
+        }
+        let set_local = |t: &mut termios::Termios, flag, on: Option<bool>| {
+            if let Some(on) = on {
+                t.local_flags.set(flag, on);
+            }
+        };
+        set_local(t, termios::LocalFlags::ICANON, self.canonical);
+        set_local(t, termios::LocalFlags::ISIG, self.signals);
+        if let Some(on) = self.flow_control {
+            t.input_flags.set(termios::InputFlags::IXON | termios::InputFlags::IXOFF, on);
+        }
+        if let Some((min, time)) = self.min_time {
+            t.control_chars[termios::SpecialCharacterIndices::VMIN as usize] = min;
+            t.control_chars[termios::SpecialCharacterIndices::VTIME as usize] = time;
+        }
+    }
+}
+
+/// A resource limit of the process, see `SpawnOptions::rlimit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// number of open file descriptors (`RLIMIT_NOFILE`)
+    OpenFiles,
+    /// CPU time in seconds (`RLIMIT_CPU`), the process gets SIGXCPU at the soft limit
+    CpuTime,
+    /// size of the virtual memory in bytes (`RLIMIT_AS`), allocations beyond fail
+    AddressSpace,
+}
+
+impl Resource {
+    // runs in the forked child
+    fn set_limit(self, soft: u64, hard: u64) -> nix::Result<()> {
+        let resource = match self {
+            Resource::OpenFiles => nix::libc::RLIMIT_NOFILE,
+            Resource::CpuTime => nix::libc::RLIMIT_CPU,
+            Resource::AddressSpace => nix::libc::RLIMIT_AS,
+        };
+        let limit = nix::libc::rlimit {
+            rlim_cur: soft as nix::libc::rlim_t,
+            rlim_max: hard as nix::libc::rlim_t,
+        };
+        Errno::result(unsafe { nix::libc::setrlimit(resource, &limit) }).map(drop)
+    }
+}
+
+/// A Linux namespace the process gets a new one of, see `SpawnOptions::unshare`
+#[cfg(target_os = "linux")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    /// network devices, addresses and ports (`CLONE_NEWNET`), only a loopback device
+    /// which is down
+    Network,
+    /// mount points (`CLONE_NEWNS`), mounting and unmounting doesn't affect the system
+    Mount,
+    /// process ids (`CLONE_NEWPID`): the process itself stays in our namespace, the first
+    /// process it starts is pid 1 of the new one
+    Pid,
+    /// user and group ids (`CLONE_NEWUSER`), lets unprivileged processes create the
+    /// other namespaces
+    User,
+}
+
+#[cfg(target_os = "linux")]
+impl Namespace {
+    fn flag(self) -> nix::sched::CloneFlags {
+        use nix::sched::CloneFlags;
+        match self {
+            Namespace::Network => CloneFlags::CLONE_NEWNET,
+            Namespace::Mount => CloneFlags::CLONE_NEWNS,
+            Namespace::Pid => CloneFlags::CLONE_NEWPID,
+            Namespace::User => CloneFlags::CLONE_NEWUSER,
+        }
+    }
+}
+
+// runs in the forked child, see `SpawnOptions::cgroup`: "0" moves the writing process
+#[cfg(target_os = "linux")]
+fn join_cgroup(cgroup: &std::path::Path) -> nix::Result<()> {
+    let fd = open(&cgroup.join("cgroup.procs"), OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+                  stat::Mode::empty())?;
+    let written = nix::unistd::write(fd, b"0");
+    close(fd)?;
+    written.map(drop)
+}
+
+// runs in the forked child, see `SpawnOptions::cpu_affinity`
+#[cfg(target_os = "linux")]
+fn set_cpu_affinity(cpus: &[usize]) -> nix::Result<()> {
+    use nix::libc::{cpu_set_t, sched_setaffinity, CPU_SET, CPU_SETSIZE};
+    let mut set: cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in cpus {
+        if cpu >= CPU_SETSIZE as usize {
+            return Err(nix::Error::Sys(Errno::EINVAL));
+        }
+        unsafe { CPU_SET(cpu, &mut set) };
+    }
+    let size = std::mem::size_of::<cpu_set_t>();
+    Errno::result(unsafe { sched_setaffinity(0, size, &set) }).map(drop)
+}
+
+/// Called with the pid of a started process, see `SpawnOptions::on_spawn`
+pub type SpawnHook = Arc<dyn Fn(Pid) + Send + Sync>;
+
+/// Called when a process was waited for, see `SpawnOptions::on_exit`
+pub type ExitHook = Arc<dyn Fn(Pid, wait::WaitStatus) + Send + Sync>;
+
+impl fmt::Debug for SpawnOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut s = f.debug_struct("SpawnOptions");
+        s.field("retry", &self.retry)
+            .field("new_session", &self.new_session)
+            .field("controlling_tty", &self.controlling_tty)
+            .field("kill_process_group", &self.kill_process_group)
+            .field("start_suspended", &self.start_suspended)
+            .field("keep_slave_open", &self.keep_slave_open)
+            .field("forward_signals", &self.forward_signals)
+            .field("read_inline", &self.read_inline)
+            .field("window_size", &self.window_size)
+            .field("terminal_mode", &self.terminal_mode)
+            .field("rlimits", &self.rlimits)
+            .field("priority", &self.priority);
+        #[cfg(target_os = "linux")]
+        s.field("cpu_affinity", &self.cpu_affinity)
+            .field("namespaces", &self.namespaces)
+            .field("cgroup", &self.cgroup);
+        s.field("on_spawn", &self.on_spawn.is_some())
+            .field("on_exit", &self.on_exit.is_some())
+            .finish()
+    }
+}
+
+impl Default for SpawnOptions {
+    fn default() -> Self {
+        SpawnOptions {
+            retry: RetryPolicy::default(),
+            new_session: true,
+            controlling_tty: true,
+            kill_process_group: false,
+            start_suspended: false,
+            keep_slave_open: false,
+            forward_signals: false,
+            read_inline: cfg!(feature = "single-threaded"),
+            window_size: None,
+            terminal_mode: TerminalMode::default(),
+            rlimits: Vec::new(),
+            priority: None,
+            #[cfg(target_os = "linux")]
+            cpu_affinity: None,
+            #[cfg(target_os = "linux")]
+            namespaces: Vec::new(),
+            #[cfg(target_os = "linux")]
+            cgroup: None,
+            on_spawn: None,
+            on_exit: None,
+        }
+    }
+}
+
+impl SpawnOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retry spawning on transient errors, e.g. when running out of ptys or file
+    /// descriptors in big parallel test suites. By default spawning is not retried.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Start the process as the leader of a new session (`setsid`), the default. Without,
+    /// it stays in the session of the caller, in a process group of its own, and only
+    /// its stdin, stdout and stderr are the pty: it never gets the pty as controlling
+    /// terminal (`controlling_tty` is ignored) and `/dev/tty` is the terminal of the
+    /// caller, if there's one. For daemons which misbehave with a controlling terminal.
+    pub fn new_session(mut self, new_session: bool) -> Self {
+        self.new_session = new_session;
+        self
+    }
+
+    /// Make the pty the controlling terminal of the process (the default). Without, the
+    /// process still runs in a session of its own but has no controlling terminal:
+    /// opening `/dev/tty` fails and control codes like Ctrl-C don't turn into signals.
+    /// Note that the first terminal the process then opens without `O_NOCTTY` becomes
+    /// its controlling terminal.
+    pub fn controlling_tty(mut self, controlling_tty: bool) -> Self {
+        self.controlling_tty = controlling_tty;
+        self
+    }
+
+    /// Kill the whole process group of the process instead of only the process itself
+    /// (`kill`, `exit` and on drop), including the processes it started, unless they
+    /// moved to a group of their own (like the jobs of an interactive shell). Whatever is
+    /// left of the group after the process died is killed with SIGKILL.
+    ///
+    /// Without, processes which ignore the SIGHUP the group gets when the process exits
+    /// (e.g. a server started with `nohup`) keep running and keep the pty open.
+    pub fn kill_process_group(mut self, kill_process_group: bool) -> Self {
+        self.kill_process_group = kill_process_group;
+        self
+    }
+
+    /// Stop the process right before it executes the program (with SIGSTOP), so you can
+    /// attach a debugger or set up watchers first. Spawning returns once it's stopped,
+    /// continue it with `PtyProcess::resume`.
+    pub fn start_suspended(mut self, start_suspended: bool) -> Self {
+        self.start_suspended = start_suspended;
+        self
+    }
+
+    /// Keep the terminal side of the pty open in the parent as well, so the output doesn't
+    /// end when the process exits. For processes whose workers open the terminal again
+    /// later (e.g. by its name) and write to it after the process itself is gone.
+    ///
+    /// Reading then never reaches EOF, not even after all processes exited: wait for
+    /// the output you expect instead, or end it with `PtyProcess::close_slave`.
+    pub fn keep_slave_open(mut self, keep_slave_open: bool) -> Self {
+        self.keep_slave_open = keep_slave_open;
+        self
+    }
+
+    /// Forward SIGINT, SIGTERM and SIGHUP which our own process receives to the process
+    /// (or its process group with `kill_process_group`), so e.g. pressing Ctrl-C while
+    /// the tests run ends the processes under test as well instead of leaving them
+    /// behind. After forwarding the signal is handled as before (by default it ends our
+    /// process).
+    ///
+    /// Installs a signal handler for the three signals on first use, which calls the
+    /// handler installed before. Handlers installed later replace it. Signals are
+    /// forwarded to at most `MAX_FORWARD_TARGETS` running processes at once.
+    pub fn forward_signals(mut self, forward_signals: bool) -> Self {
+        self.forward_signals = forward_signals;
+        self
+    }
+
+    /// Let a session read the output on the caller's thread, polling the pty with the
+    /// timeout while waiting, instead of by a reader thread (see `NBReader::new_inline`).
+    /// The default with the `single-threaded` feature. Only used by
+    /// `session::spawn_with_options`, a `PtyProcess` doesn't read by itself.
+    pub fn read_inline(mut self, read_inline: bool) -> Self {
+        self.read_inline = read_inline;
+        self
+    }
+
+    pub(crate) fn reads_inline(&self) -> bool {
+        self.read_inline
+    }
+
+    /// Start the terminal with `cols` columns and `rows` rows, so the output wraps the
+    /// same everywhere. By default the size is whatever the system gives a new pty
+    /// (usually 0x0, which most programs treat as 80x24).
+    pub fn window_size(mut self, cols: u16, rows: u16) -> Self {
+        self.window_size = Some((cols, rows));
+        self
+    }
+
+    /// Set up the terminal of the process with `mode` instead of the system's defaults
+    pub fn terminal_mode(mut self, mode: TerminalMode) -> Self {
+        self.terminal_mode = mode;
+        self
+    }
+
+    /// Limit `resource` of the process to `soft` (the hard limit `hard`) before it executes
+    /// the program, e.g. to test how it deals with running out of file descriptors or
+    /// memory. If a limit can't be set (e.g. a soft limit above the hard limit, or raising
+    /// a hard limit without privileges) the process exits with 127, like for a missing
+    /// program.
+    pub fn rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Run the process with the nice value `nice` (-20 is the highest priority, 19 the
+    /// lowest), e.g. to keep long running processes of a benchmark harness from
+    /// disturbing the measurements. Only privileged processes can go below the nice value
+    /// of our process, otherwise the process exits with 127 like for a missing program.
+    pub fn priority(mut self, nice: i32) -> Self {
+        self.priority = Some(nice);
+        self
+    }
+
+    /// Pin the process to the CPUs with the numbers in `cpus`. If none of them is
+    /// available (or a number is not below `CPU_SETSIZE`) the process exits with 127.
+    #[cfg(target_os = "linux")]
+    pub fn cpu_affinity(mut self, cpus: &[usize]) -> Self {
+        self.cpu_affinity = Some(cpus.to_vec());
+        self
+    }
+
+    /// Put the process into a new namespace of the kind `namespace` (call once per kind),
+    /// e.g. to test a program without network access or with mounts of its own. Without
+    /// privileges (`CAP_SYS_ADMIN`) this only works together with `Namespace::User`. If
+    /// unsharing fails the process exits with 127.
+    #[cfg(target_os = "linux")]
+    pub fn unshare(mut self, namespace: Namespace) -> Self {
+        self.namespaces.push(namespace);
+        self
+    }
+
+    /// Move the process into the existing cgroup at `path` (e.g.
+    /// `/sys/fs/cgroup/pids/tests`) before it executes the program, so its limits apply
+    /// from the start. The cgroup is joined before the namespaces are created. If it
+    /// can't be joined the process exits with 127.
+    #[cfg(target_os = "linux")]
+    pub fn cgroup<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.cgroup = Some(path.into());
+        self
+    }
+
+    /// Call `hook` with the pid of the process as soon as it's started (once it's
+    /// stopped with `start_suspended`), e.g. to register it with a resource tracker
+    pub fn on_spawn<F>(mut self, hook: F) -> Self
+        where F: Fn(Pid) + Send + Sync + 'static
+    {
+        self.on_spawn = Some(Arc::new(hook));
+        self
+    }
+
+    /// Call `hook` with the pid and the exit status once the process ended and was
+    /// waited for (by `status`, `wait`, `exit`, on drop, ...), e.g. to collect coverage
+    /// data it wrote
+    pub fn on_exit<F>(mut self, hook: F) -> Self
+        where F: Fn(Pid, wait::WaitStatus) + Send + Sync + 'static
+    {
+        self.on_exit = Some(Arc::new(hook));
+        self
+    }
+}
+
+/// When and how often spawning a process is retried, see `SpawnOptions::retry`
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// how many times spawning is tried at most (1 means no retry)
+    pub attempts: u32,
+    /// wait time before the first retry, doubled for every following retry
+    pub backoff: time::Duration,
+    /// errors which are worth a retry, by default `EAGAIN`, `EINTR`, `EMFILE`, `ENFILE`,
+    /// `ENOMEM` and `ENOSPC` (no more ptys on Linux)
+    pub retryable: Vec<Errno>,
+}
+
+impl RetryPolicy {
+    /// Try `attempts` times in total with a backoff starting at 50ms
+    pub fn new(attempts: u32) -> Self {
+        RetryPolicy { attempts, ..Self::default() }
+    }
+
+    fn should_retry(&self, attempt: u32, errno: Errno) -> bool {
+        attempt < self.attempts && self.retryable.contains(&errno)
+    }
+
+    fn delay(&self, attempt: u32) -> time::Duration {
+        self.backoff * 2u32.saturating_pow(attempt - 1)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 1,
+            backoff: time::Duration::from_millis(50),
+            retryable: vec![Errno::EAGAIN, Errno::EINTR, Errno::EMFILE, Errno::ENFILE,
+                            Errno::ENOMEM, Errno::ENOSPC],
+        }
+    }
+}
+
+/// The login shell of the user, `$SHELL -l` (`/bin/sh` if `SHELL` is unset) with `TERM`
+/// set to `xterm`, matching the sequences `Key` sends
+///
+/// Spawned with the default `SpawnOptions` the shell is the leader of a new session with
+/// the pty as its controlling terminal, so it runs interactively with job control.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::process::login_shell;
+/// use rexpect::session::spawn_command;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let mut p = spawn_command(login_shell(), Some(10000))?;
+/// p.send_line("echo \"term=$TERM\"; exit")?;
+/// p.exp_string("term=xterm")?;
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub fn login_shell() -> Command {
+    let shell = std::env::var_os("SHELL").filter(|shell| !shell.is_empty());
+    let mut command = Command::new(shell.as_deref().unwrap_or_else(|| "/bin/sh".as_ref()));
+    command.arg("-l").env("TERM", "xterm");
+    command
+}
+
 impl PtyProcess {
     /// Start a process in a forked pty
-    pub fn new(mut command: Command) -> Result<Self> {
-        || -> nix::Result<Self> {
-            // Open a new PTY master
-            let master_fd = posix_openpt(OFlag::O_RDWR)?;
-
-            // Allow a slave to be generated for it
-            grantpt(&master_fd)?;
-            unlockpt(&master_fd)?;
-
-            // on Linux this is the libc function, on OSX this is our implementation of ptsname_r
-            let slave_name = ptsname_r(&master_fd)?;
-
-            match fork()? {
-                ForkResult::Child => {
-                    setsid()?; // create new session with child as session leader
-                    let slave_fd = open(std::path::Path::new(&slave_name),
-                                        OFlag::O_RDWR,
-                                        stat::Mode::empty())?;
-
-                    // assign stdin, stdout, stderr to the tty, just like a terminal does
-                    dup2(slave_fd, STDIN_FILENO)?;
-                    dup2(slave_fd, STDOUT_FILENO)?;
-                    dup2(slave_fd, STDERR_FILENO)?;
-
-                    // set echo off
-                    let mut flags = termios::tcgetattr(STDIN_FILENO)?;
-                    flags.local_flags &= !termios::LocalFlags::ECHO;
-                    termios::tcsetattr(STDIN_FILENO, termios::SetArg::TCSANOW, &flags)?;
-
-                    command.exec();
-                    Err(nix::Error::last())
+    ///
+    /// The Unix extensions of `command` apply as usual. Hooks registered with
+    /// `CommandExt::pre_exec` run in the child right before the program is executed,
+    /// after the pty became its terminal (and stdin, stdout and stderr), e.g. to change
+    /// the signal mask or enter a namespace. If a hook fails the process exits with 127.
+    ///
+    /// The same goes for `CommandExt::uid` and `gid`: a harness running as root can start
+    /// the program as an unprivileged user, the pty is set up before switching.
+    ///
+    /// ```
+    /// use rexpect::process::PtyProcess;
+    /// use std::os::unix::process::CommandExt;
+    /// use std::process::Command;
+    ///
+    /// let mut command = Command::new("cat");
+    /// unsafe {
+    ///     command.pre_exec(|| {
+    ///         // e.g. unblock all signals
+    ///         Ok(())
+    ///     });
+    /// }
+    /// let process = PtyProcess::new(command).expect("could not execute cat");
+    /// ```
+    pub fn new(command: Command) -> Result<Self> {
+        Self::new_with_options(command, SpawnOptions::default())
+    }
+
+    /// Start a process in a forked pty, see `SpawnOptions` for what can be configured
+    pub fn new_with_options(mut command: Command, options: SpawnOptions) -> Result<Self> {
+        let mut attempt = 1;
+        loop {
+            match Self::fork_pty(&mut command, &options) {
+                Err(nix::Error::Sys(errno)) if options.retry.should_retry(attempt, errno) => {
+                    thread::sleep(options.retry.delay(attempt));
+                    attempt += 1;
                 }
-                ForkResult::Parent { child: child_pid } => {
-                    Ok(PtyProcess {
-                           pty: master_fd,
-                           child_pid: child_pid,
-                           kill_timeout: None,
-                       })
+                res => {
+                    let process = res.chain_err(|| format!("could not execute {:?}", command))?;
+                    if options.start_suspended {
+                        process.wait_stopped()?;
+                    }
+                    if let Some(ref on_spawn) = options.on_spawn {
+                        on_spawn(process.child_pid);
+                    }
+                    return Ok(process);
                 }
             }
-        }()
-                .chain_err(|| format!("could not execute {:?}", command))
+        }
+    }
+
+    /// Take over a process started by other means (e.g. `forkpty` in C code) which runs in
+    /// the pty with the master side `master_fd`, so it can be used like a spawned one.
+    /// `pid` must be a child of our process, otherwise waiting for it fails. The process
+    /// is ended on drop like a spawned one. `master_fd` is closed, also if this fails.
+    ///
+    /// # Safety
+    ///
+    /// `master_fd` must be an open pty master which nothing else closes, it's owned by the
+    /// `PtyProcess` from now on (like with `File::from_raw_fd`).
+    pub unsafe fn from_raw_parts(master_fd: RawFd, pid: Pid) -> Result<Self> {
+        // A `PtyMaster` can't be built from a fd, only opened. So open a new pty and dup2
+        // `master_fd` over its fd number: that closes the new pty, and the `PtyMaster` owns
+        // a copy of `master_fd` from then on. dup2 doesn't copy FD_CLOEXEC, so it's set
+        // afterwards (and not passed to posix_openpt, which macOS rejects with EINVAL).
+        let pty = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY).and_then(|pty| {
+            dup2(master_fd, pty.as_raw_fd())?;
+            fcntl(pty.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+            Ok(pty)
+        });
+        // only the copy is used, the original is closed on success and on error alike
+        let _ = close(master_fd);
+        let pty = pty.chain_err(|| "could not take over the pty")?;
+        Ok(PtyProcess {
+            pty,
+            child_pid: pid,
+            kill_timeout: None,
+            termination: Termination::default(),
+            kill_group: false,
+            slave: None,
+            waiter: WaitHandle::new(pid, None, None),
+        })
+    }
+
+    // wait until the child stopped itself, see `SpawnOptions::start_suspended`
+    fn wait_stopped(&self) -> Result<()> {
+        match self.waitpid(Some(wait::WaitPidFlag::WUNTRACED))
+            .chain_err(|| "wait: cannot read status")? {
+            wait::WaitStatus::Stopped(..) => Ok(()),
+            status => Err(format!("process exited before it was suspended: {:?}", status).into()),
+        }
+    }
+
+    /// Close the terminal side of the pty kept open with `SpawnOptions::keep_slave_open`,
+    /// so reading reaches EOF once no process has it open anymore
+    pub fn close_slave(&mut self) {
+        self.slave = None;
+    }
+
+    /// Continue a process started with `SpawnOptions::start_suspended` (or stopped
+    /// otherwise) by sending SIGCONT
+    pub fn resume(&self) -> Result<()> {
+        signal::kill(self.child_pid, signal::SIGCONT).chain_err(|| "cannot send SIGCONT")
+    }
+
+    fn fork_pty(command: &mut Command, options: &SpawnOptions) -> nix::Result<Self> {
+        // Open a new PTY master, close-on-exec so that no child process inherits it
+        let master_fd = posix_openpt(OFlag::O_RDWR)?;
+        fcntl(master_fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+
+        // Allow a slave to be generated for it
+        grantpt(&master_fd)?;
+        unlockpt(&master_fd)?;
+
+        // on Linux this is the libc function, elsewhere our implementation of ptsname_r
+        let slave_name = ptsname_r(&master_fd)?;
+
+        // set echo off before forking, otherwise input written by the parent before the
+        // child is up would be echoed back by the tty
+        let slave_fd = open(std::path::Path::new(&slave_name),
+                            OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_CLOEXEC,
+                            stat::Mode::empty())?;
+        let forked = || -> nix::Result<ForkResult> {
+            let mut flags = termios::tcgetattr(slave_fd)?;
+            options.terminal_mode.apply(&mut flags);
+            flags.local_flags &= !termios::LocalFlags::ECHO;
+            termios::tcsetattr(slave_fd, termios::SetArg::TCSANOW, &flags)?;
+            if let Some((cols, rows)) = options.window_size {
+                let size = nix::libc::winsize {
+                    ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0
+                };
+                unsafe { winsize::set(slave_fd, &size) }?;
+            }
+            fork()
+        }();
+
+        match forked {
+            Ok(ForkResult::Child) => {
+                let _ = Self::exec_child(command, &slave_name, slave_fd, options);
+                // exec failed: never return into the code of the caller in the child
+                unsafe { nix::libc::_exit(127) }
+            }
+            Ok(ForkResult::Parent { child: child_pid }) => {
+                let slave = if options.keep_slave_open {
+                    Some(unsafe { File::from_raw_fd(slave_fd) })
+                } else {
+                    close(slave_fd)?;
+                    None
+                };
+                let forwarding = if options.forward_signals {
+                    let target = if options.kill_process_group {
+                        -child_pid.as_raw()
+                    } else {
+                        child_pid.as_raw()
+                    };
+                    forwarding::register(target).map(|slot| (slot, target))
+                } else {
+                    None
+                };
+                Ok(PtyProcess {
+                       pty: master_fd,
+                       child_pid,
+                       kill_timeout: None,
+                       termination: Termination::default(),
+                       kill_group: options.kill_process_group,
+                       slave,
+                       waiter: WaitHandle::new(child_pid, forwarding, options.on_exit.clone()),
+                   })
+            }
+            Err(e) => {
+                // don't leak the fd, spawning might be retried
+                let _ = close(slave_fd);
+                Err(e)
+            }
+        }
+    }
+
+    /// runs in the forked child, only returns if something went wrong
+    fn exec_child(command: &mut Command,
+                  slave_name: &str,
+                  slave_fd: RawFd,
+                  options: &SpawnOptions)
+                  -> nix::Result<()> {
+        let mut flags = OFlag::O_RDWR | OFlag::O_CLOEXEC;
+        if options.new_session {
+            setsid()?; // create new session with child as session leader
+        } else {
+            // a group of its own, so that `kill_process_group` only hits the process
+            setpgid(Pid::from_raw(0), Pid::from_raw(0))?;
+            flags |= OFlag::O_NOCTTY;
+        }
+        // reopen the slave so that it becomes the controlling terminal
+        if !options.controlling_tty {
+            flags |= OFlag::O_NOCTTY;
+        }
+        let ctty_fd = open(std::path::Path::new(slave_name), flags, stat::Mode::empty())?;
+        close(slave_fd)?;
+        if options.new_session && options.controlling_tty {
+            // Linux does that on open already, macOS and the BSDs only with TIOCSCTTY
+            unsafe { ctty::set(ctty_fd, 0) }?;
+        }
+
+        // assign stdin, stdout, stderr to the tty, just like a terminal does
+        // (dup2 clears close-on-exec, ctty_fd itself is closed on exec)
+        dup2(ctty_fd, STDIN_FILENO)?;
+        dup2(ctty_fd, STDOUT_FILENO)?;
+        dup2(ctty_fd, STDERR_FILENO)?;
+
+        for &(resource, soft, hard) in &options.rlimits {
+            resource.set_limit(soft, hard)?;
+        }
+        if let Some(nice) = options.priority {
+            let which = nix::libc::PRIO_PROCESS;
+            Errno::result(unsafe { nix::libc::setpriority(which, 0, nice) })?;
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(ref cpus) = options.cpu_affinity {
+                set_cpu_affinity(cpus)?;
+            }
+            if let Some(ref cgroup) = options.cgroup {
+                join_cgroup(cgroup)?;
+            }
+            let namespaces = options.namespaces.iter()
+                .fold(nix::sched::CloneFlags::empty(), |flags, namespace| flags | namespace.flag());
+            if !namespaces.is_empty() {
+                nix::sched::unshare(namespaces)?;
+            }
+        }
+
+        if options.start_suspended {
+            // the parent waits for this, see `wait_stopped`
+            signal::raise(signal::SIGSTOP)?;
+        }
+
+        // exec only returns on failure
+        let _ = command.exec();
+        Err(nix::Error::last())
     }
 
     /// Get handle to pty fork for reading/writing
     pub fn get_file_handle(&self) -> File {
         // needed because otherwise fd is closed both by dropping process and reader/writer
-        let fd = dup(self.pty.as_raw_fd()).unwrap();
+        let fd = fcntl(self.pty.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(0)).unwrap();
         unsafe { File::from_raw_fd(fd) }
     }
 
-    /// At the drop of PtyProcess the running process is killed. This is blocking forever if
-    /// the process does not react to a normal kill. If kill_timeout is set the process is
-    /// `kill -9`ed after duration
+    /// Get a handle to read the output of the process. Unlike a handle from
+    /// `get_file_handle` it reports the end of the output as EOF (`Ok(0)`) instead of
+    /// failing with EIO (which is what Linux does once the process closed the terminal),
+    /// so `read_to_end` and the like just work.
+    pub fn reader(&self) -> PtyReader {
+        PtyReader { file: self.get_file_handle() }
+    }
+
+    /// Switch the pty into non-blocking mode (`O_NONBLOCK`), so reads without output
+    /// fail with `WouldBlock` instead of blocking, e.g. to register it with an event loop
+    /// like `tokio::io::unix::AsyncFd`.
+    ///
+    /// Note that the mode is shared by all handles returned by `get_file_handle`, so
+    /// don't use it together with a `PtySession` of the same process (its reader thread
+    /// expects blocking reads).
+    pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+        let fd = self.pty.as_raw_fd();
+        let flags = fcntl(fd, FcntlArg::F_GETFL).chain_err(|| "cannot get pty flags")?;
+        let mut flags = OFlag::from_bits_truncate(flags);
+        flags.set(OFlag::O_NONBLOCK, nonblocking);
+        fcntl(fd, FcntlArg::F_SETFL(flags)).chain_err(|| "cannot set pty flags")?;
+        Ok(())
+    }
+
+    /// The process id of the process, the same as `child_pid` but as the type
+    /// `std::process::Child::id` has, e.g. for logs and monitoring tools
+    pub fn pid(&self) -> u32 {
+        self.child_pid.as_raw() as u32
+    }
+
+    /// The kind of terminal the process runs in and with it what it supports
+    pub fn backend(&self) -> BackendKind {
+        BackendKind::UnixPty
+    }
+
+    /// Path of the terminal device of the process (the slave side of the pty), e.g.
+    /// `/dev/pts/3`, so other tools can attach to it like `gdb --tty`
+    pub fn pts_name(&self) -> Result<PathBuf> {
+        let name = ptsname_r(&self.pty).chain_err(|| "cannot get the name of the pts")?;
+        Ok(PathBuf::from(name))
+    }
+
+    /// Size of the terminal as (columns, rows)
+    pub fn window_size(&self) -> Result<(u16, u16)> {
+        let mut size = nix::libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        unsafe { winsize::get(self.pty.as_raw_fd(), &mut size) }
+            .chain_err(|| "cannot get window size")?;
+        Ok((size.ws_col, size.ws_row))
+    }
+
+    /// Resize the terminal to `cols` columns and `rows` rows. If the size changed the
+    /// kernel sends SIGWINCH to the process (see `notify_winch` to send it regardless).
+    pub fn set_window_size(&self, cols: u16, rows: u16) -> Result<()> {
+        let size = nix::libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+        unsafe { winsize::set(self.pty.as_raw_fd(), &size) }
+            .chain_err(|| "cannot set window size")?;
+        Ok(())
+    }
+
+    /// Resize the terminal to `cols` columns and `rows` rows and make sure the program
+    /// gets SIGWINCH, e.g. so a full screen program redraws: the kernel sends it if the
+    /// size changed, otherwise `notify_winch` does. Use `set_window_size` to leave it to
+    /// the kernel.
+    ///
+    /// This is the way to resize on every backend which `supports_resize`, so portable
+    /// code needs no platform specific calls.
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+        match self.backend() {
+            BackendKind::UnixPty => {
+                let unchanged = self.window_size()? == (cols, rows);
+                self.set_window_size(cols, rows)?;
+                if unchanged {
+                    self.notify_winch()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the terminal echoes the input back into the output
+    pub fn echo(&self) -> Result<bool> {
+        let flags = termios::tcgetattr(self.pty.as_raw_fd()).chain_err(|| "cannot get echo")?;
+        Ok(flags.local_flags.contains(termios::LocalFlags::ECHO))
+    }
+
+    /// Switch echoing the input on or off, it's off after spawning. Programs can change it
+    /// themselves (e.g. bash turns it on for its prompt), so this lasts until they do.
+    pub fn set_echo(&self, echo: bool) -> Result<()> {
+        let fd = self.pty.as_raw_fd();
+        let mut flags = termios::tcgetattr(fd).chain_err(|| "cannot set echo")?;
+        flags.local_flags.set(termios::LocalFlags::ECHO, echo);
+        termios::tcsetattr(fd, termios::SetArg::TCSANOW, &flags).chain_err(|| "cannot set echo")
+    }
+
+    /// Send SIGWINCH to the foreground process group of the terminal (the same which gets
+    /// it on resize), e.g. to make a full screen program redraw even though the size
+    /// didn't change. Falls back to the child process if there's no foreground group.
+    pub fn notify_winch(&self) -> Result<()> {
+        self.signal_foreground(signal::SIGWINCH).chain_err(|| "cannot send SIGWINCH")
+    }
+
+    /// Send SIGINT to the foreground process group of the terminal, the same as pressing
+    /// Ctrl-C does, but also when the program switched the terminal into raw mode.
+    /// Falls back to the child process if there's no foreground group.
+    pub fn interrupt(&self) -> Result<()> {
+        self.signal_foreground(signal::SIGINT).chain_err(|| "cannot send SIGINT")
+    }
+
+    fn signal_foreground(&self, sig: signal::Signal) -> nix::Result<()> {
+        match tcgetpgrp(self.pty.as_raw_fd()) {
+            Ok(pgrp) => signal::killpg(pgrp, sig),
+            Err(_) => signal::kill(self.child_pid, sig),
+        }
+    }
+
+    /// At the drop of PtyProcess the running process is killed. If kill_timeout is set the
+    /// process is `kill -9`ed after duration, without it `exit` and `kill` block forever
+    /// if the process does not react to a normal kill. Dropping never does, it uses
+    /// `DROP_KILL_TIMEOUT` then.
     pub fn set_kill_timeout(&mut self, timeout_ms: Option<u64>) {
-        self.kill_timeout = timeout_ms.and_then(|millis| Some(time::Duration::from_millis(millis)));
+        self.kill_timeout = timeout_ms.map(time::Duration::from_millis);
+    }
+
+    /// Change how `exit` and dropping end the process, `Termination::Repeat` by default
+    pub fn set_termination(&mut self, termination: Termination) {
+        self.termination = termination;
     }
 
     /// Get status of child process, nonblocking.
     ///
-    /// This method runs waitpid on the process.
-    /// This means: If you ran `exit()` before or `status()` this method will
-    /// return an Error
+    /// This method runs waitpid on the process. Once the process is reaped (by this,
+    /// `wait` or `exit`) the same status is returned again.
     ///
     /// # Example
     /// ```rust,no_run
@@ -171,22 +1174,72 @@ impl PtyProcess {
     /// ```
     ///
     pub fn status(&self) -> Option<wait::WaitStatus> {
-        if let Ok(status) = wait::waitpid(self.child_pid, Some(wait::WaitPidFlag::WNOHANG)) {
-            Some(status)
-        } else {
-            None
-        }
+        self.waitpid(Some(wait::WaitPidFlag::WNOHANG)).ok()
+    }
+
+    /// The exit status once the process exited, as `std::process::ExitStatus`. Doesn't
+    /// block, `None` while the process is running.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.status().and_then(to_exit_status)
+    }
+
+    fn waitpid(&self, options: Option<wait::WaitPidFlag>) -> nix::Result<wait::WaitStatus> {
+        self.waiter.waitpid(options)
+    }
+
+    /// A handle to end and wait for the process from elsewhere, e.g. another thread. It
+    /// shares the exit status with this process: whoever waits for the process first
+    /// keeps it for the others.
+    pub fn wait_handle(&self) -> WaitHandle {
+        self.waiter.clone()
     }
 
     /// Wait until process has exited. This is a blocking call.
     /// If the process doesn't terminate this will block forever.
     pub fn wait(&self) -> Result<wait::WaitStatus> {
-        wait::waitpid(self.child_pid, None).chain_err(|| "wait: cannot read status")
+        self.waitpid(None).chain_err(|| "wait: cannot read status")
+    }
+
+    /// Same as `wait`, but gives up after `timeout` and returns `None` if the process is
+    /// still running then.
+    pub fn wait_timeout(&self, timeout: time::Duration) -> Result<Option<wait::WaitStatus>> {
+        let start = time::Instant::now();
+        loop {
+            match self.waitpid(Some(wait::WaitPidFlag::WNOHANG))
+                .chain_err(|| "wait: cannot read status")? {
+                wait::WaitStatus::StillAlive if start.elapsed() < timeout => {
+                    thread::sleep(std::cmp::min(time::Duration::from_millis(10),
+                                                timeout - start.elapsed()))
+                }
+                wait::WaitStatus::StillAlive => return Ok(None),
+                status => return Ok(Some(status)),
+            }
+        }
     }
 
-    /// Regularly exit the process, this method is blocking until the process is dead
+    /// Same as `wait_timeout`, but returns the status as `std::process::ExitStatus`
+    /// (like `exit_status`)
+    pub fn exit_status_timeout(&self, timeout: time::Duration) -> Result<Option<ExitStatus>> {
+        Ok(self.wait_timeout(timeout)?.and_then(to_exit_status))
+    }
+
+    /// Regularly exit the process, this method is blocking until the process is dead.
+    /// See `set_termination` for how.
     pub fn exit(&mut self) -> Result<wait::WaitStatus> {
-        self.kill(signal::SIGTERM)
+        match self.termination {
+            Termination::Repeat => self.kill(signal::SIGTERM),
+            Termination::Graceful { signal, grace } => {
+                self.send_to_child(signal).chain_err(|| "failed to send signal to process")?;
+                let status = match self.wait_timeout(grace)? {
+                    Some(status) => status,
+                    None => self.kill(signal::SIGKILL)?,
+                };
+                if self.kill_group {
+                    self.kill_remaining_group();
+                }
+                Ok(status)
+            }
+        }
     }
 
     /// Nonblocking variant of `kill()` (doesn't wait for process to be killed)
@@ -205,10 +1258,22 @@ impl PtyProcess {
     /// if `kill_timeout` is set and a repeated sending of signal does not result in the process
     /// being killed, then `kill -9` is sent after the `kill_timeout` duration has elapsed.
     pub fn kill(&mut self, sig: signal::Signal) -> Result<wait::WaitStatus> {
+        let status = self.kill_child(sig);
+        if self.kill_group {
+            self.kill_remaining_group();
+        }
+        status
+    }
+
+    fn kill_child(&mut self, sig: signal::Signal) -> Result<wait::WaitStatus> {
         let start = time::Instant::now();
         loop {
-            match signal::kill(self.child_pid, sig) {
-                Ok(_) => {}
+            match self.send_to_child(sig) {
+                // a stopped process (see `SpawnOptions::start_suspended`) only handles
+                // the signal once it's continued
+                Ok(_) => {
+                    let _ = self.send_to_child(signal::SIGCONT);
+                }
                 // process was already killed before -> ignore
                 Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => {
                     return Ok(wait::WaitStatus::Exited(Pid::from_raw(0), 0))
@@ -224,27 +1289,682 @@ impl PtyProcess {
             // kill -9 if timout is reached
             if let Some(timeout) = self.kill_timeout {
                 if start.elapsed() > timeout {
-                    signal::kill(self.child_pid, signal::Signal::SIGKILL).chain_err(|| "")?
+                    self.send_to_child(signal::Signal::SIGKILL).chain_err(|| "")?
                 }
             }
         }
     }
+
+    // send to the child, or its whole group with `SpawnOptions::kill_process_group`
+    fn send_to_child(&self, sig: signal::Signal) -> nix::Result<()> {
+        if self.kill_group {
+            // the child leads its process group, so its pid is the group id
+            signal::killpg(self.child_pid, sig)
+        } else {
+            signal::kill(self.child_pid, sig)
+        }
+    }
+
+    // `exit` for `drop`: every wait is bounded and errors are ignored, the worst case is
+    // a process left behind
+    fn exit_on_drop(&mut self) {
+        let (sig, grace, repeat) = match self.termination {
+            Termination::Repeat => {
+                (signal::SIGTERM, self.kill_timeout.unwrap_or(DROP_KILL_TIMEOUT), true)
+            }
+            Termination::Graceful { signal, grace } => (signal, grace, false),
+        };
+        let start = time::Instant::now();
+        let interval = time::Duration::from_millis(100);
+        let mut last_signal: Option<time::Instant> = None;
+        let exited = loop {
+            let due = match last_signal {
+                None => true,
+                Some(t) => repeat && t.elapsed() >= interval,
+            };
+            if due {
+                if self.send_to_child(sig).is_err() {
+                    break true;
+                }
+                let _ = self.send_to_child(signal::SIGCONT);
+                last_signal = Some(time::Instant::now());
+            }
+            match self.wait_timeout(time::Duration::from_millis(10)) {
+                Ok(None) if start.elapsed() < grace => {}
+                Ok(None) => break false,
+                _ => break true,
+            }
+        };
+        if !exited && self.send_to_child(signal::SIGKILL).is_ok() {
+            let _ = self.wait_timeout(DROP_REAP_TIMEOUT);
+        }
+        if self.kill_group {
+            self.kill_remaining_group();
+        }
+    }
+
+    fn stop_forwarding(&self) {
+        self.waiter.stop_forwarding();
+    }
+
+    // SIGKILL whatever is left of the process group once the child is gone
+    fn kill_remaining_group(&self) {
+        // fails with ESRCH if the group is empty, which is what we want anyway
+        let _ = signal::killpg(self.child_pid, signal::Signal::SIGKILL);
+    }
 }
 
-impl Drop for PtyProcess {
-    fn drop(&mut self) {
-        match self.status() {
-            Some(wait::WaitStatus::StillAlive) => {
-                self.exit().expect("cannot exit");
+/// The output of a process, see `PtyProcess::reader`
+#[derive(Debug)]
+pub struct PtyReader {
+    file: File,
+}
+
+impl io::Read for PtyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.file.read(buf) {
+            // the other side of the pty is closed, but Linux may report this before the
+            // last output is readable: only a second EIO in a row is the end of it
+            Err(ref e) if e.raw_os_error() == Some(EIO) => match self.file.read(buf) {
+                Err(ref e) if e.raw_os_error() == Some(EIO) => Ok(0),
+                result => result,
+            },
+            result => result,
+        }
+    }
+}
+
+impl AsRawFd for PtyReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl fmt::Debug for PtyProcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // `status()` would reap the process, signal 0 only checks that it's there
+        // (which includes having exited without being waited for)
+        let alive = signal::kill(self.child_pid, None).is_ok();
+        f.debug_struct("PtyProcess")
+            .field("child_pid", &self.child_pid)
+            .field("alive", &alive)
+            .field("pty", &self.pty.as_raw_fd())
+            .field("kill_timeout", &self.kill_timeout)
+            .finish()
+    }
+}
+
+// the status of an exited process as `std::process::ExitStatus`, see `exit_status`
+fn to_exit_status(status: wait::WaitStatus) -> Option<ExitStatus> {
+    match status {
+        wait::WaitStatus::Exited(_, code) => Some(ExitStatus::from_raw(code << 8)),
+        wait::WaitStatus::Signaled(_, sig, core_dumped) => {
+            let core = if core_dumped { 0x80 } else { 0 };
+            Some(ExitStatus::from_raw(sig as i32 | core))
+        }
+        _ => None,
+    }
+}
+
+/// Waits for a process and keeps its exit status, see `PtyProcess::wait_handle`
+#[derive(Clone)]
+pub struct WaitHandle {
+    pid: Pid,
+    // the status once the process is reaped, later waits return it again
+    reaped: Arc<Mutex<Option<wait::WaitStatus>>>,
+    // slot and target, see `SpawnOptions::forward_signals`
+    forwarding: Option<(usize, i32)>,
+    on_exit: Option<ExitHook>,
+}
+
+impl WaitHandle {
+    fn new(pid: Pid, forwarding: Option<(usize, i32)>, on_exit: Option<ExitHook>) -> Self {
+        WaitHandle { pid, reaped: Arc::new(Mutex::new(None)), forwarding, on_exit }
+    }
+
+    /// The pid of the process
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Same as `PtyProcess::status`
+    pub fn status(&self) -> Option<wait::WaitStatus> {
+        self.waitpid(Some(wait::WaitPidFlag::WNOHANG)).ok()
+    }
+
+    /// Same as `PtyProcess::wait`
+    pub fn wait(&self) -> Result<wait::WaitStatus> {
+        self.waitpid(None).chain_err(|| "wait: cannot read status")
+    }
+
+    /// Send `sig` to the process, unless it was waited for already (then its pid may
+    /// belong to another process). Returns whether the signal was sent.
+    pub fn signal(&self, sig: signal::Signal) -> Result<bool> {
+        let reaped = self.reaped.lock().unwrap();
+        if reaped.is_some() {
+            return Ok(false);
+        }
+        signal::kill(self.pid, sig).chain_err(|| "failed to send signal to process")?;
+        Ok(true)
+    }
+
+    // waitpid on the child, runs the exit hook once it's reaped
+    fn waitpid(&self, options: Option<wait::WaitPidFlag>) -> nix::Result<wait::WaitStatus> {
+        if let Some(status) = *self.reaped.lock().unwrap() {
+            return Ok(status);
+        }
+        // not locked while waiting, so `status` on other threads doesn't block meanwhile
+        let status = match wait::waitpid(self.pid, options) {
+            // another thread reaped it in the meantime
+            Err(nix::Error::Sys(Errno::ECHILD)) => {
+                return self.reaped.lock().unwrap().ok_or(nix::Error::Sys(Errno::ECHILD));
+            }
+            result => result?,
+        };
+        match status {
+            wait::WaitStatus::Exited(..) | wait::WaitStatus::Signaled(..) => {
+                *self.reaped.lock().unwrap() = Some(status);
+                // the pid may be reused from now on
+                self.stop_forwarding();
+                if let Some(ref on_exit) = self.on_exit {
+                    on_exit(self.pid, status);
+                }
             }
             _ => {}
         }
+        Ok(status)
+    }
+
+    fn stop_forwarding(&self) {
+        if let Some((slot, target)) = self.forwarding {
+            forwarding::unregister(slot, target);
+        }
+    }
+}
+
+impl fmt::Debug for WaitHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WaitHandle")
+            .field("pid", &self.pid)
+            .field("reaped", &*self.reaped.lock().unwrap())
+            .finish()
+    }
+}
+
+/// The master side of the pty, e.g. to poll it or to register it with an event loop.
+/// It stays owned by the process, use `get_file_handle` for a handle of your own.
+impl AsRawFd for PtyProcess {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pty.as_raw_fd()
+    }
+}
+
+impl Drop for PtyProcess {
+    fn drop(&mut self) {
+        if let Some(wait::WaitStatus::StillAlive) = self.status() {
+            self.exit_on_drop();
+        } else if self.kill_group {
+            self.kill_remaining_group();
+        }
+        self.stop_forwarding();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::new(3);
+        assert!(policy.should_retry(1, Errno::EMFILE));
+        assert!(policy.should_retry(2, Errno::EAGAIN));
+        assert!(!policy.should_retry(3, Errno::EAGAIN));
+        assert!(!policy.should_retry(1, Errno::ENOENT));
+        assert_eq!(time::Duration::from_millis(100), policy.delay(2));
+        assert!(!RetryPolicy::default().should_retry(1, Errno::EAGAIN));
+    }
+
+    #[test]
+    fn test_backend() {
+        let process = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        let backend = process.backend();
+        assert_eq!(BackendKind::UnixPty, backend);
+        assert!(backend.supports_resize() && backend.supports_signals());
+        assert!(!backend.separate_stderr());
+        process.resize(120, 50).expect("cannot resize");
+        assert_eq!((120, 50), process.window_size().unwrap());
+    }
+
+    #[test]
+    fn test_nonblocking() {
+        let process = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        process.set_nonblocking(true).expect("cannot set non-blocking");
+        let mut f = process.get_file_handle();
+        let mut buf = [0u8; 16];
+        let err = f.read(&mut buf).expect_err("read should not block");
+        assert_eq!(std::io::ErrorKind::WouldBlock, err.kind());
+        process.set_nonblocking(false).expect("cannot set blocking");
+        f.write_all(b"hi\n").unwrap();
+        let n = f.read(&mut buf).unwrap();
+        assert_eq!(b"hi\r\n", &buf[..n]);
+    }
+
+    #[test]
+    fn test_as_raw_fd() {
+        use nix::poll::{poll, PollFd, PollFlags};
+        let process = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        let mut fds = [PollFd::new(process.as_raw_fd(), PollFlags::POLLIN)];
+        assert_eq!(0, poll(&mut fds, 100).unwrap());
+        process.get_file_handle().write_all(b"hi\n").unwrap();
+        assert_eq!(1, poll(&mut fds, 1000).unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_no_inherited_fds() {
+        let first = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        let _handle = first.get_file_handle();
+        let second = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        // wait until cat runs, the tty echoes nothing before
+        let mut f = second.get_file_handle();
+        f.write_all(b"hi\n").unwrap();
+        let mut buf = [0u8; 16];
+        assert!(f.read(&mut buf).unwrap() > 0);
+        for process in &[first, second] {
+            let mut fds: Vec<String> = std::fs::read_dir(format!("/proc/{}/fd", process.child_pid))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            fds.sort();
+            assert_eq!(vec!["0", "1", "2"], fds);
+        }
+    }
+
+    #[test]
+    fn test_wait_timeout() {
+        let mut command = Command::new("sleep");
+        command.arg("0.3");
+        let process = PtyProcess::new(command).expect("could not execute sleep");
+        assert_eq!(None, process.wait_timeout(time::Duration::from_millis(50)).unwrap());
+        assert_eq!(Some(wait::WaitStatus::Exited(process.child_pid, 0)),
+                   process.wait_timeout(time::Duration::from_secs(5)).unwrap());
+    }
+
+    #[test]
+    fn test_exit_status_timeout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 0.3; exit 4"]);
+        let process = PtyProcess::new(command).expect("could not execute sh");
+        assert_eq!(None, process.exit_status_timeout(time::Duration::from_millis(50)).unwrap());
+        let status = process.exit_status_timeout(time::Duration::from_secs(5)).unwrap();
+        assert_eq!(Some(4), status.and_then(|s| s.code()));
+        // from the cached status once reaped
+        assert_eq!(status, process.exit_status_timeout(time::Duration::ZERO).unwrap());
+    }
+
+    #[test]
+    fn test_status_while_waiting() {
+        let mut command = Command::new("sleep");
+        command.arg("0.5");
+        let process = PtyProcess::new(command).expect("could not execute sleep");
+        let status = thread::scope(|s| {
+            let waiting = s.spawn(|| process.wait());
+            thread::sleep(time::Duration::from_millis(100));
+            // doesn't wait for the blocking `wait` on the other thread
+            let start = time::Instant::now();
+            assert_eq!(Some(wait::WaitStatus::StillAlive), process.status());
+            assert_eq!(None, process.wait_timeout(time::Duration::from_millis(10)).unwrap());
+            assert!(start.elapsed() < time::Duration::from_millis(200));
+            waiting.join().unwrap().unwrap()
+        });
+        assert_eq!(wait::WaitStatus::Exited(process.child_pid, 0), status);
+        assert_eq!(Some(status), process.status());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_kill_process_group() {
+        // zombies count as dead, nobody might reap them in a container
+        let alive = |pid: &str| {
+            std::fs::read_to_string(format!("/proc/{}/stat", pid))
+                .map(|stat| !stat.contains(") Z "))
+                .unwrap_or(false)
+        };
+        for &kill_group in &[false, true] {
+            let mut command = Command::new("bash");
+            // the group gets SIGHUP when bash exits, like with nohup the sleep ignores it
+            command.args(["-c", "trap '' HUP; sleep 100 & echo $!; wait"]);
+            let options = SpawnOptions::new().kill_process_group(kill_group);
+            let process = PtyProcess::new_with_options(command, options)
+                .expect("could not execute bash");
+            let mut pid = String::new();
+            BufReader::new(process.get_file_handle()).read_line(&mut pid).unwrap();
+            let pid = pid.trim().to_string();
+            drop(process);
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(!kill_group, alive(&pid), "kill_process_group({})", kill_group);
+            if !kill_group {
+                signal::kill(Pid::from_raw(pid.parse().unwrap()), signal::SIGKILL).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (spawned, exited) = (events.clone(), events.clone());
+        let options = SpawnOptions::new()
+            .on_spawn(move |pid| spawned.lock().unwrap().push(format!("spawn {}", pid)))
+            .on_exit(move |pid, status| {
+                exited.lock().unwrap().push(format!("exit {} {:?}", pid, status))
+            });
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        let process = PtyProcess::new_with_options(command, options).expect("could not execute sh");
+        let pid = process.child_pid;
+        assert_eq!(wait::WaitStatus::Exited(pid, 3), process.wait().unwrap());
+        drop(process);
+        assert_eq!(vec![format!("spawn {}", pid), format!("exit {} Exited(Pid({}), 3)", pid, pid)],
+                   *events.lock().unwrap());
+    }
+
+    #[test]
+    fn test_graceful_termination() {
+        let dir = tempfile::tempdir().unwrap();
+        for &(grace, flushed) in &[(2000, true), (100, false)] {
+            let profile = dir.path().join(format!("profile-{}", grace));
+            let mut command = Command::new("bash");
+            command.args(["-c", &format!("trap 'sleep 0.3; echo flushed > {}; exit 0' TERM; \
+                                          echo ready; while :; do sleep 0.05; done",
+                                         profile.display())]);
+            let mut process = PtyProcess::new(command).expect("could not execute bash");
+            let mut ready = String::new();
+            BufReader::new(process.get_file_handle()).read_line(&mut ready).unwrap();
+            process.set_termination(Termination::Graceful {
+                signal: signal::SIGTERM,
+                grace: time::Duration::from_millis(grace),
+            });
+            let status = process.exit().unwrap();
+            if flushed {
+                assert_eq!(wait::WaitStatus::Exited(process.child_pid, 0), status);
+            } else {
+                assert_eq!(wait::WaitStatus::Signaled(process.child_pid, signal::SIGKILL, false),
+                           status);
+            }
+            assert_eq!(flushed, profile.exists());
+        }
+    }
+
+    #[test]
+    fn test_reader_eof() {
+        let mut command = Command::new("echo");
+        command.arg("hello");
+        let process = PtyProcess::new(command).expect("could not execute echo");
+        let mut output = String::new();
+        process.reader().read_to_string(&mut output).unwrap();
+        assert_eq!("hello\r\n", output);
+        let mut buf = [0u8; 16];
+        assert_eq!(0, process.reader().read(&mut buf).unwrap());
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "exit 3"]);
+        let process = PtyProcess::new(command).expect("could not execute sh");
+        let status = process.wait().unwrap();
+        assert_eq!(wait::WaitStatus::Exited(process.child_pid, 3), status);
+        // reaped already, the status stays
+        assert_eq!(status, process.wait().unwrap());
+        assert_eq!(Some(status), process.status());
+        assert_eq!(Some(3), process.exit_status().unwrap().code());
+
+        let mut process = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        assert_eq!(None, process.exit_status());
+        process.exit().unwrap();
+        let status = process.exit_status().unwrap();
+        assert_eq!((None, Some(signal::SIGTERM as i32)), (status.code(), status.signal()));
+    }
+
+    #[test]
+    fn test_pts_name() {
+        let process = PtyProcess::new(Command::new("tty")).expect("could not execute tty");
+        let name = process.pts_name().unwrap();
+        let mut output = String::new();
+        BufReader::new(process.get_file_handle()).read_line(&mut output).unwrap();
+        assert_eq!(format!("{}\r\n", name.display()), output);
+    }
+
+    #[test]
+    fn test_pid() {
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo $$"]);
+        let process = PtyProcess::new(command).expect("could not execute bash");
+        let mut output = String::new();
+        let _ = process.reader().read_to_string(&mut output);
+        assert_eq!(format!("{}\r\n", process.pid()), output);
+    }
+
+    #[test]
+    fn test_initial_window_size() {
+        let mut command = Command::new("stty");
+        command.arg("size");
+        let options = SpawnOptions::new().window_size(100, 40);
+        let process = PtyProcess::new_with_options(command, options)
+            .expect("could not execute stty");
+        assert_eq!((100, 40), process.window_size().unwrap());
+        let mut output = String::new();
+        BufReader::new(process.get_file_handle()).read_line(&mut output).unwrap();
+        assert_eq!("40 100\r\n", output);
+    }
+
+    #[test]
+    fn test_pre_exec() {
+        let mut command = Command::new("echo");
+        command.arg("executed");
+        unsafe {
+            command.pre_exec(|| {
+                // stdout is the pty already
+                match nix::unistd::write(STDOUT_FILENO, b"hook\n") {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(std::io::Error::last_os_error()),
+                }
+            });
+        }
+        let process = PtyProcess::new(command).expect("could not execute echo");
+        let mut output = String::new();
+        let _ = process.reader().read_to_string(&mut output);
+        assert_eq!("hook\r\nexecuted\r\n", output);
+
+        let mut command = Command::new("echo");
+        unsafe {
+            command.pre_exec(|| Err(std::io::Error::from_raw_os_error(nix::libc::EPERM)));
+        }
+        let process = PtyProcess::new(command).expect("could not fork");
+        assert_eq!(wait::WaitStatus::Exited(process.child_pid, 127), process.wait().unwrap());
+    }
+
+    #[test]
+    fn test_uid_gid() {
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let mut command = Command::new("id");
+        command.uid(65534).gid(65534);
+        let process = PtyProcess::new(command).expect("could not execute id");
+        let mut output = String::new();
+        let _ = process.reader().read_to_string(&mut output);
+        assert!(output.starts_with("uid=65534(") && output.contains(" gid=65534("), "{}", output);
+    }
+
+    #[test]
+    fn test_rlimit() {
+        let mut command = Command::new("bash");
+        command.args(["-c", "ulimit -Sn; ulimit -Hn; ulimit -t; ulimit -v"]);
+        let options = SpawnOptions::new()
+            .rlimit(Resource::OpenFiles, 32, 64)
+            .rlimit(Resource::CpuTime, 30, 30)
+            .rlimit(Resource::AddressSpace, 1 << 32, 1 << 32);
+        let process = PtyProcess::new_with_options(command, options)
+            .expect("could not execute bash");
+        let mut output = String::new();
+        let _ = process.reader().read_to_string(&mut output);
+        // `ulimit -v` is in KiB
+        assert_eq!("32\r\n64\r\n30\r\n4194304\r\n", output);
+
+        // the soft limit can't be above the hard limit
+        let options = SpawnOptions::new().rlimit(Resource::OpenFiles, 64, 32);
+        let process = PtyProcess::new_with_options(Command::new("true"), options)
+            .expect("could not fork");
+        assert_eq!(wait::WaitStatus::Exited(process.child_pid, 127), process.wait().unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_priority() {
+        let mut command = Command::new("bash");
+        command.args(["-c", "nice; grep Cpus_allowed_list /proc/self/status"]);
+        let options = SpawnOptions::new().priority(7).cpu_affinity(&[0]);
+        let process = PtyProcess::new_with_options(command, options)
+            .expect("could not execute bash");
+        let mut output = String::new();
+        let _ = process.reader().read_to_string(&mut output);
+        assert_eq!("7\r\nCpus_allowed_list:\t0\r\n", output);
+
+        let options = SpawnOptions::new().cpu_affinity(&[usize::MAX]);
+        let process = PtyProcess::new_with_options(Command::new("true"), options)
+            .expect("could not fork");
+        assert_eq!(wait::WaitStatus::Exited(process.child_pid, 127), process.wait().unwrap());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_namespaces() {
+        if !nix::unistd::geteuid().is_root() {
+            return;
+        }
+        let ns = |options: SpawnOptions| -> String {
+            let mut command = Command::new("readlink");
+            command.args(["/proc/self/ns/net", "/proc/self/ns/mnt"]);
+            let process = PtyProcess::new_with_options(command, options)
+                .expect("could not execute readlink");
+            let mut output = String::new();
+            let _ = process.reader().read_to_string(&mut output);
+            output
+        };
+        let lines = |s: String| s.lines().map(String::from).collect::<Vec<_>>();
+        let ours = lines(ns(SpawnOptions::new()));
+        let net = lines(ns(SpawnOptions::new().unshare(Namespace::Network)));
+        assert_eq!(2, net.len(), "{:?}", net);
+        assert_ne!(ours[0], net[0]);
+        assert_eq!(ours[1], net[1]);
+
+        // a cgroup of our own, only where cgroups can be created
+        let cgroup = PathBuf::from(format!("/sys/fs/cgroup/pids/rexpect-{}", std::process::id()));
+        if std::fs::create_dir(&cgroup).is_ok() {
+            let mut command = Command::new("cat");
+            command.arg("/proc/self/cgroup");
+            let options = SpawnOptions::new().cgroup(&cgroup);
+            let process = PtyProcess::new_with_options(command, options)
+                .expect("could not execute cat");
+            let mut output = String::new();
+            let _ = process.reader().read_to_string(&mut output);
+            process.wait().unwrap();
+            let _ = std::fs::remove_dir(&cgroup);
+            let expected = format!(":pids:/rexpect-{}\r\n", std::process::id());
+            assert!(output.contains(&expected), "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_echo() {
+        let process = PtyProcess::new(Command::new("cat")).expect("could not execute cat");
+        let mut f = BufReader::new(process.get_file_handle());
+        let mut read_line = || {
+            let mut line = String::new();
+            f.read_line(&mut line).unwrap();
+            line
+        };
+        let mut w = process.get_file_handle();
+        assert!(!process.echo().unwrap());
+        process.set_echo(true).unwrap();
+        assert!(process.echo().unwrap());
+        w.write_all(b"one\n").unwrap();
+        assert_eq!(("one\r\n".to_string(), "one\r\n".to_string()), (read_line(), read_line()));
+        process.set_echo(false).unwrap();
+        w.write_all(b"two\nthree\n").unwrap();
+        assert_eq!(("two\r\n".to_string(), "three\r\n".to_string()), (read_line(), read_line()));
+    }
+
+    #[test]
+    fn test_terminal_mode() {
+        // the settings printed by `stty -a`, e.g. "-icanon" or "min = 1;"
+        let stty = |mode: TerminalMode| -> Vec<String> {
+            let mut command = Command::new("stty");
+            command.arg("-a");
+            let options = SpawnOptions::new().terminal_mode(mode);
+            let process = PtyProcess::new_with_options(command, options)
+                .expect("could not execute stty");
+            let mut output = String::new();
+            let _ = process.reader().read_to_string(&mut output);
+            output.replace(" = ", "=").split_whitespace().map(String::from).collect()
+        };
+        let output = stty(TerminalMode::new());
+        for flag in &["icanon", "-echo"] {
+            assert!(output.iter().any(|s| s == flag), "{} missing in {:?}", flag, output);
+        }
+        let mode = TerminalMode::new()
+            .canonical(false)
+            .signals(false)
+            .flow_control(false)
+            .min_time(1, 5);
+        let output = stty(mode);
+        for flag in &["-icanon", "-isig", "-ixon", "-ixoff", "min=1;", "time=5;"] {
+            assert!(output.iter().any(|s| s == flag), "{} missing in {:?}", flag, output);
+        }
+        let output = stty(TerminalMode::new().raw(true).signals(true));
+        for flag in &["-icanon", "isig", "-opost", "-echo"] {
+            assert!(output.iter().any(|s| s == flag), "{} missing in {:?}", flag, output);
+        }
+    }
+
+    #[test]
+    fn test_controlling_tty() {
+        for &(controlling_tty, expected) in &[(true, "yes"), (false, "no")] {
+            let mut command = Command::new("sh");
+            command.args(["-c", "if (exec 3</dev/tty) 2>/dev/null; then echo yes; else echo no; fi"]);
+            let options = SpawnOptions::new().controlling_tty(controlling_tty);
+            let process = PtyProcess::new_with_options(command, options)
+                .expect("could not execute sh");
+            let mut output = String::new();
+            let _ = BufReader::new(process.get_file_handle()).read_line(&mut output);
+            assert_eq!(format!("{}\r\n", expected), output);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_new_session() {
+        // pid, process group and session of the shell
+        let ids = |new_session: bool| -> Vec<i32> {
+            let mut command = Command::new("bash");
+            command.args(["-c", "read -r pid _ _ _ pgrp sid _ < /proc/self/stat; \
+                                 echo $pid $pgrp $sid"]);
+            let options = SpawnOptions::new().new_session(new_session);
+            let process = PtyProcess::new_with_options(command, options)
+                .expect("could not execute bash");
+            let mut output = String::new();
+            let _ = BufReader::new(process.get_file_handle()).read_line(&mut output);
+            output.split_whitespace().map(|id| id.parse().unwrap()).collect()
+        };
+        let own = ids(true);
+        assert_eq!(vec![own[0]; 3], own);
+        let shared = ids(false);
+        let our_session = nix::unistd::getsid(None).unwrap().as_raw();
+        assert_eq!(vec![shared[0], shared[0], our_session], shared);
+    }
+
     use std::io::{BufReader, LineWriter};
     use nix::sys::{wait, signal};
     use std::io::prelude::*;
@@ -258,7 +1978,7 @@ mod tests {
             let f = process.get_file_handle();
             let mut writer = LineWriter::new(&f);
             let mut reader = BufReader::new(&f);
-            writer.write(b"hello cat\n")?;
+            writer.write_all(b"hello cat\n")?;
             let mut buf = String::new();
             reader.read_line(&mut buf)?;
             assert_eq!(buf, "hello cat\r\n");
@@ -1,12 +1,20 @@
 //! Unblocking reader which supports waiting for strings/regexes and EOF to be present
 
-use std::io::{self, BufReader};
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::io::{self, BufReader, SeekFrom};
 use std::io::prelude::*;
-use std::sync::mpsc::{channel, Receiver};
+use std::path::Path;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::{thread, result};
 use std::{time, fmt};
 use crate::errors::*; // load error-chain
 pub use regex::Regex;
+use nix::libc::EIO;
+use nix::poll::{poll, PollFd, PollFlags};
+use crate::ansi::{self, Color};
 
 #[derive(Debug)]
 enum PipeError {
@@ -16,27 +24,37 @@ enum PipeError {
 #[derive(Debug)]
 enum PipedChar {
     Char(u8),
-    EOF,
+    Bytes(Vec<u8>),
+    Eof,
+    // no data, wakes up a waiting reader (see `CancelHandle`)
+    Wake,
 }
 
+#[derive(Debug, Clone)]
 pub enum ReadUntil {
     String(String),
     Regex(Regex),
     EOF,
     NBytes(usize),
     Any(Vec<ReadUntil>),
+    /// text printed in the given foreground color, see `PtySession::exp_colored`
+    Colored(String, Color),
+    /// raw bytes, which need not be valid UTF-8
+    Bytes(Vec<u8>),
 }
 
 impl fmt::Display for ReadUntil {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let printable = match self {
-            &ReadUntil::String(ref s) if s == "\n" => "\\n (newline)".to_string(),
-            &ReadUntil::String(ref s) if s == "\r" => "\\r (carriage return)".to_string(),
-            &ReadUntil::String(ref s) => format!("\"{}\"", s),
-            &ReadUntil::Regex(ref r) => format!("Regex: \"{}\"", r),
-            &ReadUntil::EOF => "EOF (End of File)".to_string(),
-            &ReadUntil::NBytes(n) => format!("reading {} bytes", n),
-            &ReadUntil::Any(ref v) => {
+        let printable = match *self {
+            ReadUntil::String(ref s) if s == "\n" => "\\n (newline)".to_string(),
+            ReadUntil::String(ref s) if s == "\r" => "\\r (carriage return)".to_string(),
+            ReadUntil::String(ref s) => format!("\"{}\"", s),
+            ReadUntil::Regex(ref r) => format!("Regex: \"{}\"", r),
+            ReadUntil::EOF => "EOF (End of File)".to_string(),
+            ReadUntil::NBytes(n) => format!("reading {} bytes", n),
+            ReadUntil::Colored(ref s, color) => format!("\"{}\" in {}", s, color),
+            ReadUntil::Bytes(ref b) => format!("bytes {:02x?}", b),
+            ReadUntil::Any(ref v) => {
                 let mut res = Vec::new();
                 for r in v {
                     res.push(r.to_string());
@@ -48,6 +66,27 @@ impl fmt::Display for ReadUntil {
     }
 }
 
+/// What happens to the output when a needle matched, see `NBReader::read_until_with`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Consume {
+    /// remove the output before the match and the match itself (the default)
+    #[default]
+    All,
+    /// remove only the output before the match, the next call sees the match again
+    UntilMatch,
+    /// remove nothing, like peeking at the output
+    Nothing,
+}
+
+/// The output before the needle, see `NBReader::read_until_to`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    /// the output stayed within the threshold and was collected
+    Collected(String),
+    /// the output grew beyond the threshold, this many bytes were written into the writer
+    Streamed(usize),
+}
+
 /// find first occurrence of needle within buffer
 ///
 /// # Arguments:
@@ -61,30 +100,33 @@ impl fmt::Display for ReadUntil {
 /// 1. position before match (0 in case of EOF and Nbytes)
 /// 2. position after match
 pub fn find(needle: &ReadUntil, buffer: &str, eof: bool) -> Option<(usize, usize)> {
-    match needle {
-        &ReadUntil::String(ref s) => buffer.find(s).and_then(|pos| Some((pos, pos + s.len()))),
-        &ReadUntil::Regex(ref pattern) => {
-            if let Some(mat) = pattern.find(buffer) {
-                Some((mat.start(), mat.end()))
-            } else {
-                None
-            }
-        }
-        &ReadUntil::EOF => if eof { Some((0, buffer.len())) } else { None },
-        &ReadUntil::NBytes(n) => {
-            if n <= buffer.len() {
-                Some((0, n))
-            } else if eof && buffer.len() > 0 {
+    match *needle {
+        ReadUntil::String(ref s) => buffer.find(s).map(|pos| (pos, pos + s.len())),
+        ReadUntil::Regex(ref pattern) => pattern.find(buffer).map(|mat| (mat.start(), mat.end())),
+        ReadUntil::EOF => if eof { Some((0, buffer.len())) } else { None },
+        ReadUntil::NBytes(n) => {
+            // every char of the buffer is one byte of output (see `to_bytes`), but chars
+            // above 0x7f take two bytes within the string
+            let end = buffer.char_indices()
+                .map(|(pos, _)| pos)
+                .chain(std::iter::once(buffer.len()))
+                .nth(n);
+            match end {
+                Some(end) => Some((0, end)),
                 // reached almost end of buffer, return string, even though it will be
                 // smaller than the wished n bytes
-                Some((0, buffer.len()))
-            } else {
-                None
+                None if eof && !buffer.is_empty() => Some((0, buffer.len())),
+                None => None,
             }
         }
-        &ReadUntil::Any(ref any) => {
+        ReadUntil::Colored(ref s, color) => ansi::find_colored(s, color, buffer),
+        ReadUntil::Bytes(ref b) => {
+            let s = from_bytes(b);
+            buffer.find(&s).map(|pos| (pos, pos + s.len()))
+        }
+        ReadUntil::Any(ref any) => {
             for read_until in any {
-                if let Some(pos_tuple) = find(&read_until, buffer, eof) {
+                if let Some(pos_tuple) = find(read_until, buffer, eof) {
                     return Some(pos_tuple);
                 }
             }
@@ -93,16 +135,125 @@ pub fn find(needle: &ReadUntil, buffer: &str, eof: bool) -> Option<(usize, usize
     }
 }
 
+// printable form of the needles of `read_until_any`, for errors
+fn expected(needles: &[ReadUntil]) -> String {
+    needles.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// The reader stores every byte of the output as one char (`U+0000` to `U+00FF`), so
+/// binary output survives unchanged. This turns a string returned by the reader back into
+/// the bytes the process wrote.
+pub fn to_bytes(s: &str) -> Vec<u8> {
+    s.chars().map(|c| c as u8).collect()
+}
+
+/// Opposite of `to_bytes`: the representation of `bytes` within the reader's buffer
+pub fn from_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+// add new output to the buffer, through the filter of `set_strip_escapes`
+fn append(buffer: &mut String, filter: &mut Option<ansi::EscapeFilter>, output: &str) {
+    match *filter {
+        Some(ref mut filter) => filter.push(output, buffer),
+        None => buffer.push_str(output),
+    }
+}
+
+/// The last `max_chars` chars of `s` with special chars escaped (`\r`, `\u{1b}`, ...),
+/// prefixed with `...` if it was cut
+pub(crate) fn escaped_tail(s: &str, max_chars: usize) -> String {
+    let count = s.chars().count();
+    let tail: String = s.chars().skip(count.saturating_sub(max_chars)).collect();
+    let prefix = if count > max_chars { "..." } else { "" };
+    format!("{}\"{}\"", prefix, tail.escape_debug())
+}
+
 /// Non blocking reader
 ///
 /// Typically you'd need that to check for output of a process without blocking your thread.
 /// Internally a thread is spawned and the output is read ahead so when
 /// calling `read_line` or `read_until` it reads from an internal buffer
+/// (unless created with `new_inline`)
+///
+/// Output which was written before the process exited is never lost: it stays in the buffer
+/// and can be matched by later calls, only once a needle can't be found in what is left
+/// `read_until` fails with `ErrorKind::EOF`.
 pub struct NBReader {
     reader: Receiver<result::Result<PipedChar, PipeError>>,
+    // read on the caller's thread instead of by a reader thread, see `new_inline`
+    inline: Option<File>,
+    // kept so that other sources (see `tail_file`) can feed the same stream
+    sender: Sender<result::Result<PipedChar, PipeError>>,
+    // threads of attached sources exit as soon as this is dropped
+    alive: Arc<()>,
     buffer: String,
+    // see `set_strip_escapes`
+    escape_filter: Option<ansi::EscapeFilter>,
     eof: bool,
     timeout: Option<time::Duration>,
+    deadline: Option<time::Instant>,
+    fail_patterns: Vec<ReadUntil>,
+    // set by `CancelHandle::cancel`
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The state of the matcher of an `NBReader`: the output read but not yet consumed and
+/// the settings affecting matches, see `NBReader::freeze`
+#[derive(Debug, Clone)]
+pub struct ReaderSnapshot {
+    pub buffer: String,
+    pub eof: bool,
+    pub timeout: Option<time::Duration>,
+    pub fail_patterns: Vec<ReadUntil>,
+}
+
+impl ReaderSnapshot {
+    /// Create a reader in the frozen state which continues with the output in `rest`,
+    /// e.g. the rest of a recording to re-run `read_until` calls against. With the
+    /// `single-threaded` feature `rest` is read up to its end right away instead of by
+    /// a reader thread.
+    pub fn thaw<R: Read + Send + 'static>(&self, rest: R) -> NBReader {
+        let timeout = self.timeout.map(|t| t.as_millis() as u64);
+        #[cfg(not(feature = "single-threaded"))]
+        let mut reader = NBReader::new(rest, timeout);
+        #[cfg(feature = "single-threaded")]
+        let mut reader = {
+            let (sender, rx) = channel();
+            NBReader::from_channel(rx, sender, None, timeout)
+        };
+        reader.timeout = self.timeout;
+        reader.buffer.push_str(&self.buffer);
+        reader.eof = self.eof;
+        reader.fail_patterns = self.fail_patterns.clone();
+        #[cfg(feature = "single-threaded")]
+        {
+            let mut rest = rest;
+            let mut output = Vec::new();
+            // same as the reader thread: a read error ends the output
+            let _ = rest.read_to_end(&mut output);
+            reader.buffer.push_str(&from_bytes(&output));
+            reader.eof = true;
+        }
+        reader
+    }
+}
+
+/// Aborts a blocking `read_until` (and the `exp_*` calls built on it) from another
+/// thread, see `NBReader::cancel_handle`
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+    sender: Sender<result::Result<PipedChar, PipeError>>,
+}
+
+impl CancelHandle {
+    /// Abort the call which is waiting right now (or the next one if none is) with an
+    /// `ErrorKind::Cancelled` error. The output read so far stays in the buffer.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let _ = self.sender.send(Ok(PipedChar::Wake));
+    }
 }
 
 impl NBReader {
@@ -116,22 +267,33 @@ impl NBReader {
     ///  + `Some(millis)`: after millis millisecons a timeout error is raised
     pub fn new<R: Read + Send + 'static>(f: R, timeout: Option<u64>) -> NBReader {
         let (tx, rx) = channel();
+        let sender = tx.clone();
 
         // spawn a thread which reads one char and sends it to tx
         thread::spawn(move || {
             let _ = || -> Result<()> {
                 let mut reader = BufReader::new(f);
                 let mut byte = [0u8];
+                let mut eio = false;
                 loop {
                     match reader.read(&mut byte) {
                         Ok(0) => {
-                            let _ = tx.send(Ok(PipedChar::EOF)).chain_err(|| "cannot send")?;
+                            tx.send(Ok(PipedChar::Eof)).chain_err(|| "cannot send")?;
                             break;
                         }
                         Ok(_) => {
+                            eio = false;
                             tx.send(Ok(PipedChar::Char(byte[0])))
                                 .chain_err(|| "cannot send")?;
                         }
+                        // Linux reports EIO as soon as the child closed the terminal, at times
+                        // before its last output became readable: read once more to get it
+                        Err(ref error) if error.raw_os_error() == Some(EIO) && !eio => eio = true,
+                        // EIO twice in a row: the other side of the pty is closed
+                        Err(ref error) if error.raw_os_error() == Some(EIO) => {
+                            tx.send(Ok(PipedChar::Eof)).chain_err(|| "cannot send")?;
+                            break;
+                        }
                         Err(error) => {
                             tx.send(Err(PipeError::IO(error)))
                                 .chain_err(|| "cannot send")?;
@@ -143,35 +305,162 @@ impl NBReader {
             // don't do error handling as on an error it was most probably
             // the main thread which exited (remote hangup)
         });
+        NBReader::from_channel(rx, sender, None, timeout)
+    }
+
+    /// Same as `new`, but without a reader thread: the output is read on the caller's
+    /// thread whenever the buffer is checked, waiting for output is done with `poll`.
+    ///
+    /// For environments which don't allow spawning threads. `f` needs to be something
+    /// pollable like a pty or a pipe. Note that output is only read while you call
+    /// `read_until` and friends, so a process writing more than the pty buffer can hold
+    /// in between is blocked until you read again.
+    pub fn new_inline(f: File, timeout: Option<u64>) -> NBReader {
+        let (sender, rx) = channel();
+        NBReader::from_channel(rx, sender, Some(f), timeout)
+    }
+
+    fn from_channel(reader: Receiver<result::Result<PipedChar, PipeError>>,
+                    sender: Sender<result::Result<PipedChar, PipeError>>,
+                    inline: Option<File>,
+                    timeout: Option<u64>)
+                    -> NBReader {
         // allocate string with a initial capacity of 1024, so when appending chars
         // we don't need to reallocate memory often
         NBReader {
-            reader: rx,
+            reader,
+            inline,
+            sender,
+            alive: Arc::new(()),
             buffer: String::with_capacity(1024),
+            escape_filter: None,
             eof: false,
-            timeout: timeout.and_then(|millis| Some(time::Duration::from_millis(millis))),
+            timeout: timeout.map(time::Duration::from_millis),
+            deadline: None,
+            fail_patterns: Vec::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Register a pattern which aborts `read_until` with an `ErrorKind::FailPattern` error
+    /// as soon as it shows up in the output before the needle does, e.g. `panic!` or
+    /// `Segmentation fault`. Saves you from waiting for the full timeout when the process
+    /// already told you that it failed.
+    pub fn add_fail_pattern(&mut self, pattern: ReadUntil) {
+        self.fail_patterns.push(pattern);
+    }
+
+    /// Remove all patterns registered with `add_fail_pattern`
+    pub fn clear_fail_patterns(&mut self) {
+        self.fail_patterns.clear();
+    }
+
+    /// Drop all escape sequences (colors, cursor movements, window titles, ...) from the
+    /// output read from now on, so only the printed text is matched. `ReadUntil::Colored`
+    /// never matches then.
+    pub fn set_strip_escapes(&mut self, strip: bool) {
+        self.escape_filter = if strip { Some(ansi::EscapeFilter::new()) } else { None };
+    }
+
+    /// find the fail pattern which matches first within the buffer and return
+    /// its printable form together with the match positions
+    fn find_fail_pattern(&self) -> Option<(String, (usize, usize))> {
+        self.fail_patterns
+            .iter()
+            .filter_map(|pattern| find(pattern, &self.buffer, self.eof)
+                .map(|pos| (pattern.to_string(), pos)))
+            .min_by_key(|&(_, pos)| pos.0)
+    }
+
     /// reads all available chars from the read channel and stores them in self.buffer
     fn read_into_buffer(&mut self) -> Result<()> {
-        if self.eof {
-            return Ok(());
-        }
+        self.read_inline();
         while let Ok(from_channel) = self.reader.try_recv() {
-            match from_channel {
-                Ok(PipedChar::Char(c)) => self.buffer.push(c as char),
-                Ok(PipedChar::EOF) => self.eof = true,
-                // this is just from experience, e.g. "sleep 5" returns the other error which
-                // most probably means that there is no stdout stream at all -> send EOF
-                // this only happens on Linux, not on OSX
-                Err(PipeError::IO(ref err)) if err.kind() == io::ErrorKind::Other => {
-                    self.eof = true
+            self.receive(from_channel);
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, from_channel: result::Result<PipedChar, PipeError>) {
+        match from_channel {
+            Ok(PipedChar::Char(c)) => {
+                append(&mut self.buffer, &mut self.escape_filter, &(c as char).to_string())
+            }
+            Ok(PipedChar::Bytes(bytes)) => {
+                append(&mut self.buffer, &mut self.escape_filter, &from_bytes(&bytes))
+            }
+            Ok(PipedChar::Eof) => self.eof = true,
+            Ok(PipedChar::Wake) => {}
+            // this is just from experience, e.g. "sleep 5" returns the other error which
+            // most probably means that there is no stdout stream at all -> send EOF
+            // this only happens on Linux, not on OSX
+            // (newer Rust versions don't report EIO as `ErrorKind::Other` anymore)
+            Err(PipeError::IO(ref err)) if err.kind() == io::ErrorKind::Other
+                || err.raw_os_error() == Some(EIO) => {
+                self.eof = true
+            }
+            // discard other errors
+            Err(_) => {}
+        }
+    }
+
+    /// read everything which is available without blocking from the inline source
+    fn read_inline(&mut self) {
+        let mut buf = [0u8; 1024];
+        let mut eio = false;
+        while !self.eof {
+            let f = match self.inline {
+                Some(ref mut f) => f,
+                None => return,
+            };
+            let mut fds = [PollFd::new(f.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, 0) {
+                Ok(n) if n > 0 => {}
+                _ => return,
+            }
+            match f.read(&mut buf) {
+                Ok(0) => self.eof = true,
+                Ok(n) => {
+                    eio = false;
+                    append(&mut self.buffer, &mut self.escape_filter, &from_bytes(&buf[..n]));
                 }
-                // discard other errors
-                Err(_) => {}
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // the first EIO may come before the last output is readable (see `new`)
+                Err(ref err) if err.raw_os_error() == Some(EIO) && !eio => eio = true,
+                // same as in read_into_buffer: EIO means the other side of the pty is closed
+                Err(ref err) if err.kind() == io::ErrorKind::Other
+                    || err.raw_os_error() == Some(EIO) => self.eof = true,
+                Err(_) => return,
             }
         }
+    }
+
+    /// wait for at most `duration` for new output, returns early as soon as there's output
+    /// (or a `CancelHandle` wakes us up)
+    fn wait(&mut self, duration: time::Duration) {
+        match self.inline {
+            Some(ref f) if !self.eof => {
+                let mut fds = [PollFd::new(f.as_raw_fd(), PollFlags::POLLIN)];
+                let _ = poll(&mut fds, duration.as_millis() as i32);
+            }
+            _ => {
+                if let Ok(from_channel) = self.reader.recv_timeout(duration) {
+                    self.receive(from_channel);
+                }
+            }
+        }
+    }
+
+    /// A handle to abort blocking calls of this reader from another thread
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle { cancelled: self.cancelled.clone(), sender: self.sender.clone() }
+    }
+
+    // fails with `ErrorKind::Cancelled` if `CancelHandle::cancel` was called
+    fn check_cancelled<F: FnOnce() -> String>(&self, expected: F) -> Result<()> {
+        if self.cancelled.swap(false, Ordering::SeqCst) {
+            return Err(ErrorKind::Cancelled(expected(), self.buffer.clone()).into());
+        }
         Ok(())
     }
 
@@ -194,7 +483,8 @@ impl NBReader {
     ///
     /// Note that when used with a tty the lines end with \r\n
     ///
-    /// Returns error if EOF is reached before the needle could be found.
+    /// Returns error if EOF is reached before the needle could be found or if a fail
+    /// pattern (see `add_fail_pattern`) shows up before the needle.
     ///
     /// # Example with line reading, byte reading, regex and EOF reading.
     ///
@@ -222,46 +512,358 @@ impl NBReader {
     /// ```
     ///
     pub fn read_until(&mut self, needle: &ReadUntil) -> Result<(String, String)> {
+        self.read_until_any(std::slice::from_ref(needle))
+            .map(|(_, first, second)| (first, second))
+    }
+
+    /// Same as `read_until`, but `consume` decides which part of the output is removed,
+    /// e.g. `Consume::Nothing` lets two calls look at overlapping output. The returned
+    /// strings are the same for every policy.
+    pub fn read_until_with(&mut self, needle: &ReadUntil, consume: Consume)
+                           -> Result<(String, String)> {
+        let (first, second) = self.read_until(needle)?;
+        self.unread(&first, &second, consume);
+        Ok((first, second))
+    }
+
+    // put back what `consume` says shouldn't have been removed by a match of
+    // `first` followed by `second`
+    pub(crate) fn unread(&mut self, first: &str, second: &str, consume: Consume) {
+        match consume {
+            Consume::All => {}
+            Consume::UntilMatch => self.buffer.insert_str(0, second),
+            Consume::Nothing => self.buffer.insert_str(0, &format!("{}{}", first, second)),
+        }
+    }
+
+    /// Same as `read_until` with `ReadUntil::Any`, but additionally returns the index of the
+    /// needle which matched. Needles are tried in order, the first one found wins.
+    pub fn read_until_any(&mut self, needles: &[ReadUntil]) -> Result<(usize, String, String)> {
         let start = time::Instant::now();
 
         loop {
-            self.read_into_buffer()?;
-            if let Some(tuple_pos) = find(needle, &self.buffer, self.eof) {
-                let first = self.buffer.drain(..tuple_pos.0).collect();
-                let second = self.buffer.drain(..tuple_pos.1 - tuple_pos.0).collect();
-                return Ok((first, second));
+            if let Some(found) = self.try_read_until_any(needles)? {
+                return Ok(found);
             }
+            self.check_cancelled(|| expected(needles))?;
+
+            // ran into timeout
+            if let Some(waited) = self.timed_out(start) {
+                return Err(ErrorKind::Timeout(expected(needles),
+                                              self.buffer.clone()
+                                                  .replace("\n", "`\\n`\n")
+                                                  .replace("\r", "`\\r`")
+                                                  .replace('\u{1b}', "`^`"),
+                                              waited)
+                                   .into());
+            }
+            // nothing matched: wait a little
+            self.wait(time::Duration::from_millis(100));
+        }
+    }
+
+    /// Same as `read_until`, but doesn't block: returns `None` if the needle isn't in
+    /// the output which is available right now
+    pub fn try_read_until(&mut self, needle: &ReadUntil) -> Result<Option<(String, String)>> {
+        self.try_read_until_any(std::slice::from_ref(needle))
+            .map(|found| found.map(|(_, first, second)| (first, second)))
+    }
+
+    // one round of `read_until_any`: `None` if nothing matched yet
+    fn try_read_until_any(&mut self, needles: &[ReadUntil])
+                          -> Result<Option<(usize, String, String)>> {
+        self.read_into_buffer()?;
+        let found = needles
+            .iter()
+            .enumerate()
+            .filter_map(|(i, needle)| find(needle, &self.buffer, self.eof).map(|pos| (i, pos)))
+            .next();
+
+        // a fail pattern showed up before the needle: consume the output up to and
+        // including the failure so the next call doesn't trip over it again
+        if let Some((pattern, fail_pos)) = self.find_fail_pattern() {
+            if found.map_or(true, |(_, pos)| fail_pos.0 < pos.0) {
+                let got = self.buffer.drain(..fail_pos.1).collect();
+                return Err(ErrorKind::FailPattern(expected(needles), pattern, got).into());
+            }
+        }
+
+        if let Some((index, tuple_pos)) = found {
+            let first = self.buffer.drain(..tuple_pos.0).collect();
+            let second = self.buffer.drain(..tuple_pos.1 - tuple_pos.0).collect();
+            return Ok(Some((index, first, second)));
+        }
+
+        // reached end of stream and didn't match -> error
+        // we don't know the reason of eof yet, so we provide an empty string
+        // this will be filled out in session::exp()
+        if self.eof {
+            return Err(ErrorKind::EOF(expected(needles), self.buffer.clone(), None).into());
+        }
+        Ok(None)
+    }
+
+    /// The timeout of `read_until` and friends
+    pub fn timeout(&self) -> Option<time::Duration> {
+        self.timeout
+    }
+
+    /// Change the timeout of `read_until` and friends, `None` waits forever
+    pub fn set_timeout(&mut self, timeout: Option<time::Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// The deadline of `read_until` and friends, see `set_deadline`
+    pub fn deadline(&self) -> Option<time::Instant> {
+        self.deadline
+    }
+
+    /// Make `read_until` and friends fail with a timeout error once `deadline` passed,
+    /// in addition to the timeout of each call. Unlike the timeout the deadline is shared
+    /// by all calls until it's changed.
+    pub fn set_deadline(&mut self, deadline: Option<time::Instant>) {
+        self.deadline = deadline;
+    }
+
+    // `Some(waited)` if the timeout of a call started at `start` or the deadline passed.
+    // `waited` is the timeout, or the time waited if it was the deadline
+    fn timed_out(&self, start: time::Instant) -> Option<time::Duration> {
+        let waited = start.elapsed();
+        match self.timeout {
+            Some(timeout) if waited > timeout => Some(timeout),
+            _ if self.deadline.is_some_and(|d| time::Instant::now() >= d) => Some(waited),
+            _ => None,
+        }
+    }
 
-            // reached end of stream and didn't match -> error
-            // we don't know the reason of eof yet, so we provide an empty string
-            // this will be filled out in session::exp()
+    /// Wait until `needle` shows up (blocking!) without consuming anything, return the
+    /// position of the match within `buffered()`.
+    ///
+    /// Fails on EOF or timeout the same way as `read_until`.
+    pub fn wait_until(&mut self, needle: &ReadUntil) -> Result<(usize, usize)> {
+        let start = time::Instant::now();
+        loop {
+            self.read_into_buffer()?;
+            if let Some(pos) = find(needle, &self.buffer, self.eof) {
+                return Ok(pos);
+            }
             if self.eof {
                 return Err(ErrorKind::EOF(needle.to_string(), self.buffer.clone(), None).into());
             }
+            self.check_cancelled(|| needle.to_string())?;
+            if let Some(waited) = self.timed_out(start) {
+                return Err(ErrorKind::Timeout(needle.to_string(), self.buffer.clone(), waited)
+                               .into());
+            }
+            self.wait(time::Duration::from_millis(100));
+        }
+    }
 
-            // ran into timeout
-            if let Some(timeout) = self.timeout {
-                if start.elapsed() > timeout {
-                    return Err(ErrorKind::Timeout(needle.to_string(),
-                                                  self.buffer.clone()
-                                                      .replace("\n", "`\\n`\n")
-                                                      .replace("\r", "`\\r`")
-                                                      .replace('\u{1b}', "`^`"),
-                                                  timeout)
-                                       .into());
+    /// Remove `range` (as returned by `wait_until`) from the buffered output
+    pub(crate) fn discard(&mut self, range: std::ops::Range<usize>) {
+        self.buffer.drain(range);
+    }
+
+    /// Read until `needle` is found (blocking!) and return the output before it, unless
+    /// that grows beyond `threshold` bytes: then all of it is written into `writer` while
+    /// reading instead of collecting it in memory. The needle itself is consumed but
+    /// neither returned nor written. With a `threshold` of 0 everything is written.
+    ///
+    /// Only the last few chars which could be the start of the needle are held back, so
+    /// this works for output of any size. Fail patterns are checked before the output is
+    /// written, with fail patterns registered the output is written line by line so they
+    /// are found within a line. The timeout restarts whenever there's new output.
+    pub fn read_until_to<W: Write>(&mut self, needle: &str, threshold: usize, writer: &mut W)
+                                   -> Result<Capture> {
+        let mut start = time::Instant::now();
+        let mut len = self.buffer.len();
+        // bytes written so far, `None` while the output is collected
+        let mut written = None;
+        // the buffer holds one char per byte of output, so the needle is counted in chars
+        let needle_chars = needle.chars().count();
+        let expected = || format!("\"{}\"", needle);
+
+        loop {
+            self.read_into_buffer()?;
+            if self.buffer.len() != len {
+                len = self.buffer.len();
+                start = time::Instant::now();
+            }
+            let found = self.buffer.find(needle);
+            if let Some((pattern, fail_pos)) = self.find_fail_pattern() {
+                if found.map_or(true, |pos| fail_pos.0 < pos) {
+                    let got = self.buffer.drain(..fail_pos.1).collect();
+                    return Err(ErrorKind::FailPattern(expected(), pattern, got).into());
                 }
             }
-            // nothing matched: wait a little
-            thread::sleep(time::Duration::from_millis(100));
+            let mut until = match found {
+                Some(pos) => pos,
+                None => {
+                    // hold back the last chars, they could be the start of the needle
+                    let keep = needle_chars.saturating_sub(1);
+                    let mut until = self.buffer.char_indices().rev().take(keep).last()
+                        .map_or(self.buffer.len(), |(pos, _)| pos);
+                    // and the incomplete line, it could be the start of a fail pattern
+                    if !self.fail_patterns.is_empty() {
+                        until = self.buffer[..until].rfind('\n').map_or(0, |pos| pos + 1);
+                    }
+                    until
+                }
+            };
+            if written.is_none() && self.buffer[..until].chars().count() > threshold {
+                written = Some(0);
+            }
+            if let Some(ref mut written) = written {
+                if until > 0 {
+                    let bytes = to_bytes(&self.buffer.drain(..until).collect::<String>());
+                    writer.write_all(&bytes).chain_err(|| "cannot write output")?;
+                    *written += bytes.len();
+                    len = self.buffer.len();
+                    until = 0;
+                }
+            }
+            if found.is_some() {
+                let before = self.buffer.drain(..until).collect();
+                let end = self.buffer.char_indices().nth(needle_chars)
+                    .map_or(self.buffer.len(), |(pos, _)| pos);
+                self.buffer.drain(..end);
+                return match written {
+                    Some(written) => {
+                        writer.flush().chain_err(|| "cannot flush output")?;
+                        Ok(Capture::Streamed(written))
+                    }
+                    None => Ok(Capture::Collected(before)),
+                };
+            }
+
+            if self.eof {
+                return Err(ErrorKind::EOF(expected(), self.buffer.clone(), None).into());
+            }
+            self.check_cancelled(expected)?;
+            if let Some(waited) = self.timed_out(start) {
+                return Err(ErrorKind::Timeout(expected(), self.buffer.clone(), waited).into());
+            }
+            self.wait(time::Duration::from_millis(100));
+        }
+    }
+
+    /// Read until the process didn't write anything for `idle` (blocking!) and return
+    /// everything which was not read yet.
+    ///
+    /// Handy for programs which don't print a reliable pattern when they are done.
+    /// Returns early on EOF, and with a timeout error if the process never stops writing
+    /// within the timeout.
+    pub fn read_until_idle(&mut self, idle: time::Duration) -> Result<String> {
+        let start = time::Instant::now();
+        let mut last_data = start;
+        let mut len = self.buffer.len();
+        // poll often enough that we don't overshoot `idle` by much
+        let interval = std::cmp::min(idle / 4, time::Duration::from_millis(100));
+
+        loop {
+            self.read_into_buffer()?;
+            if self.buffer.len() != len {
+                len = self.buffer.len();
+                last_data = time::Instant::now();
+            }
+            if self.eof || last_data.elapsed() >= idle {
+                return Ok(self.buffer.drain(..).collect());
+            }
+            self.check_cancelled(|| format!("idle for {} ms", idle.as_millis()))?;
+            if let Some(waited) = self.timed_out(start) {
+                return Err(ErrorKind::Timeout(format!("idle for {} ms", idle.as_millis()),
+                                              self.buffer.clone(),
+                                              waited)
+                                   .into());
+            }
+            self.wait(interval);
         }
     }
 
+    /// Follow the file at `path` (like `tail -f`) and merge every new line into the stream,
+    /// prefixed with the file name in brackets, e.g. `[server.log] listening on port 80`.
+    ///
+    /// Only lines written after this call are merged. If the file doesn't exist yet it is
+    /// followed from the start as soon as it is created. Lines are merged as a whole, but
+    /// they can end up in the middle of a line the process is currently writing.
+    pub fn tail_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref().to_path_buf();
+        let tag = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(format!("cannot tail {}: not a file", path.display()).into()),
+        };
+        let mut pos = match path.metadata() {
+            Ok(meta) => meta.len(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).chain_err(|| format!("cannot tail {}", path.display())),
+        };
+        let tx = self.sender.clone();
+        let alive: Weak<()> = Arc::downgrade(&self.alive);
+
+        thread::spawn(move || {
+            let mut partial = Vec::new();
+            // the reader was dropped -> stop following
+            while alive.upgrade().is_some() {
+                if let Ok(mut f) = File::open(&path) {
+                    // file was truncated or rotated -> start over
+                    if f.metadata().map(|meta| meta.len() < pos).unwrap_or(false) {
+                        pos = 0;
+                    }
+                    if f.seek(SeekFrom::Start(pos)).is_ok() {
+                        if let Ok(n) = f.read_to_end(&mut partial) {
+                            pos += n as u64;
+                        }
+                    }
+                }
+                while let Some(newline) = partial.iter().position(|&c| c == b'\n') {
+                    let mut line = format!("[{}] ", tag).into_bytes();
+                    line.extend(partial.drain(..=newline));
+                    if tx.send(Ok(PipedChar::Bytes(line))).is_err() {
+                        return;
+                    }
+                }
+                thread::sleep(time::Duration::from_millis(100));
+            }
+        });
+        Ok(())
+    }
+
+    /// Append `data` to the internal buffer as if it was read from the file, after
+    /// everything which was read so far.
+    ///
+    /// Useful to merge output from other sources into the stream or to test needles
+    /// without a process.
+    pub fn inject(&mut self, data: &[u8]) {
+        // discard eventual errors, EOF will be handled in read_until correctly
+        let _ = self.read_into_buffer();
+        append(&mut self.buffer, &mut self.escape_filter, &from_bytes(data));
+    }
+
+    /// Take a snapshot of the matcher state (including all output available right now),
+    /// see `ReaderSnapshot::thaw`
+    pub fn freeze(&mut self) -> ReaderSnapshot {
+        // discard eventual errors, EOF will be handled in read_until correctly
+        let _ = self.read_into_buffer();
+        ReaderSnapshot {
+            buffer: self.buffer.clone(),
+            eof: self.eof,
+            timeout: self.timeout,
+            fail_patterns: self.fail_patterns.clone(),
+        }
+    }
+
+    /// The output which was read ahead but not yet consumed by `read_until` and friends
+    /// (without reading anything new)
+    pub fn buffered(&self) -> &str {
+        &self.buffer
+    }
+
     /// Try to read one char from internal buffer. Returns None if
     /// no char is ready, Some(char) otherwise. This is nonblocking
     pub fn try_read(&mut self) -> Option<char> {
         // discard eventual errors, EOF will be handled in read_until correctly
         let _ = self.read_into_buffer();
-        if self.buffer.len() > 0 {
+        if !self.buffer.is_empty() {
             self.buffer.drain(..1).last()
         } else {
             None
@@ -269,9 +871,32 @@ impl NBReader {
     }
 }
 
+impl fmt::Debug for NBReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NBReader")
+            .field("buffer", &format_args!("{}", escaped_tail(&self.buffer, 64)))
+            .field("eof", &self.eof)
+            .field("timeout", &self.timeout)
+            .field("inline", &self.inline.is_some())
+            .field("fail_patterns", &self.fail_patterns)
+            .finish()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::os::unix::io::{FromRawFd, RawFd};
+    use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+    // close-on-exec, other tests spawn processes concurrently (macOS has no pipe2)
+    fn pipe() -> (RawFd, RawFd) {
+        let (read_fd, write_fd) = nix::unistd::pipe().expect("cannot create pipe");
+        for &fd in &[read_fd, write_fd] {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).expect("cannot set close-on-exec");
+        }
+        (read_fd, write_fd)
+    }
 
     #[test]
     fn test_expect_melon() {
@@ -282,9 +907,146 @@ mod tests {
                        .expect("cannot read line"));
         // check for EOF
         match r.read_until(&ReadUntil::NBytes(10)) {
-            Ok(_) => assert!(false),
+            Ok(_) => panic!(),
             Err(Error(ErrorKind::EOF(_, _, _), _)) => {}
-            Err(Error(_, _)) => assert!(false),
+            Err(Error(_, _)) => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_bytes() {
+        let f = io::Cursor::new(vec![0x7f, b'E', b'L', b'F', 0x00, 0xff, 0xfe, 0x01]);
+        let mut r = NBReader::new(f, None);
+        let (before, needle) = r.read_until(&ReadUntil::Bytes(vec![0xff, 0xfe]))
+            .expect("bytes not found");
+        assert_eq!(vec![0x7f, b'E', b'L', b'F', 0x00], to_bytes(&before));
+        assert_eq!(vec![0xff, 0xfe], to_bytes(&needle));
+        let (_, rest) = r.read_until(&ReadUntil::EOF).expect("EOF not reached");
+        assert_eq!(vec![0x01], to_bytes(&rest));
+    }
+
+    #[test]
+    fn test_inline() {
+        let (read_fd, write_fd) = pipe();
+        let mut writer = unsafe { File::from_raw_fd(write_fd) };
+        let mut r = NBReader::new_inline(unsafe { File::from_raw_fd(read_fd) }, Some(1000));
+        writer.write_all(b"first line\nsecond").unwrap();
+        assert_eq!(("first line".to_string(), "\n".to_string()),
+                   r.read_until(&ReadUntil::String("\n".to_string())).expect("line"));
+        assert_eq!(None, r.read_until(&ReadUntil::String("third".to_string())).ok());
+        drop(writer);
+        assert_eq!(("".to_string(), "second".to_string()),
+                   r.read_until(&ReadUntil::EOF).expect("EOF"));
+    }
+
+    #[test]
+    fn test_read_until_with() {
+        let f = io::Cursor::new("one two three");
+        let mut r = NBReader::new(f, None);
+        let two = ReadUntil::String("two".to_string());
+        assert_eq!(("one ".to_string(), "two".to_string()),
+                   r.read_until_with(&two, Consume::Nothing).unwrap());
+        assert_eq!(("one ".to_string(), "two".to_string()),
+                   r.read_until_with(&two, Consume::UntilMatch).unwrap());
+        assert_eq!(("".to_string(), "two".to_string()),
+                   r.read_until_with(&two, Consume::All).unwrap());
+        assert_eq!(" three", r.read_until(&ReadUntil::EOF).unwrap().1);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let (read_fd, write_fd) = pipe();
+        let mut writer = unsafe { File::from_raw_fd(write_fd) };
+        let mut r = NBReader::new(unsafe { File::from_raw_fd(read_fd) }, Some(10_000));
+        writer.write_all(b"so far").unwrap();
+        let handle = r.cancel_handle();
+        thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(100));
+            handle.cancel();
+        });
+        let start = time::Instant::now();
+        match r.read_until(&ReadUntil::String("never".to_string())) {
+            Err(Error(ErrorKind::Cancelled(_, got), _)) => assert_eq!("so far", got),
+            res => panic!("expected cancel, got {:?}", res),
+        }
+        assert!(start.elapsed() < time::Duration::from_secs(1));
+        // only the call which was waiting is cancelled
+        writer.write_all(b" and more").unwrap();
+        assert_eq!(("so far and ".to_string(), "more".to_string()),
+                   r.read_until(&ReadUntil::String("more".to_string())).expect("more"));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let (read_fd, write_fd) = pipe();
+        let mut writer = unsafe { File::from_raw_fd(write_fd) };
+        let mut r = NBReader::new(unsafe { File::from_raw_fd(read_fd) }, Some(1000));
+        writer.write_all(b"one\ntwo\n").unwrap();
+        r.add_fail_pattern(ReadUntil::String("error".to_string()));
+        r.read_until(&ReadUntil::String("one".to_string())).unwrap();
+        r.wait_until(&ReadUntil::String("two\n".to_string())).unwrap();
+        let snapshot = r.freeze();
+        assert_eq!("\ntwo\n", snapshot.buffer);
+
+        let mut replay = snapshot.thaw(io::Cursor::new("error\n"));
+        assert_eq!(("\n".to_string(), "two".to_string()),
+                   replay.read_until(&ReadUntil::String("two".to_string())).unwrap());
+        match replay.read_until(&ReadUntil::String("three".to_string())) {
+            Err(Error(ErrorKind::FailPattern(..), _)) => {}
+            res => panic!("expected the fail pattern, got {:?}", res),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "single-threaded")]
+    fn test_thaw_single_threaded() {
+        use std::sync::Mutex;
+        // remembers which threads read the recording
+        struct Recording(io::Cursor<&'static str>, Arc<Mutex<Vec<thread::ThreadId>>>);
+        impl Read for Recording {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.1.lock().unwrap().push(thread::current().id());
+                self.0.read(buf)
+            }
+        }
+        let readers = Arc::new(Mutex::new(Vec::new()));
+        let snapshot = ReaderSnapshot {
+            buffer: String::new(),
+            eof: false,
+            timeout: Some(time::Duration::from_millis(1000)),
+            fail_patterns: Vec::new(),
+        };
+        let mut replay = snapshot.thaw(Recording(io::Cursor::new("one\ntwo"), readers.clone()));
+        assert_eq!("one\ntwo", replay.buffered());
+        assert_eq!(("one".to_string(), "\n".to_string()),
+                   replay.read_until(&ReadUntil::String("\n".to_string())).unwrap());
+        let readers = readers.lock().unwrap();
+        assert!(!readers.is_empty());
+        assert!(readers.iter().all(|&id| id == thread::current().id()), "{:?}", readers);
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut r = NBReader::new(io::Cursor::new(""), Some(1000));
+        r.inject(b"\x1b[1mbold\x1b[0m\r\n");
+        let debug = format!("{:?}", r);
+        assert!(debug.contains(r#"buffer: "\u{1b}[1mbold\u{1b}[0m\r\n""#), "{}", debug);
+        assert_eq!("...\"ab\\n\"", escaped_tail("xyzab\n", 3));
+    }
+
+    #[test]
+    fn test_error_display() {
+        let got = "\x1b[31mfailed\x1b[0m\r\n".to_string();
+        let escaped = r#""\u{1b}[31mfailed\u{1b}[0m\r\n""#;
+        let errors: Vec<Error> = vec![
+            ErrorKind::EOF("\"ok\"".to_string(), got.clone(), None).into(),
+            ErrorKind::Cancelled("\"ok\"".to_string(), got.clone()).into(),
+            ErrorKind::FailPattern("\"ok\"".to_string(), "\"failed\"".to_string(), got).into(),
+        ];
+        for error in errors {
+            let display = error.to_string();
+            assert!(display.contains(escaped), "{}", display);
+            assert!(!display.contains('\x1b'), "{}", display);
         }
     }
 
@@ -328,6 +1090,115 @@ mod tests {
                    r.read_until(&ReadUntil::EOF).expect("reading until EOF"));
     }
 
+    #[test]
+    fn test_fail_pattern() {
+        let f = io::Cursor::new("starting up\nSegmentation fault\nready");
+        let mut r = NBReader::new(f, None);
+        r.add_fail_pattern(ReadUntil::String("Segmentation fault".to_string()));
+        match r.read_until(&ReadUntil::String("ready".to_string())) {
+            Err(Error(ErrorKind::FailPattern(_, _, got), _)) => {
+                assert_eq!("starting up\nSegmentation fault", got)
+            }
+            other => panic!("expected fail pattern error, got {:?}", other),
+        }
+        // the failure was consumed, so the rest of the output can still be read
+        assert_eq!(("\n".to_string(), "ready".to_string()),
+                   r.read_until(&ReadUntil::String("ready".to_string())).expect("ready"));
+    }
+
+    #[test]
+    fn test_fail_pattern_after_needle() {
+        let f = io::Cursor::new("ready\npanicked at");
+        let mut r = NBReader::new(f, None);
+        r.add_fail_pattern(ReadUntil::String("panicked".to_string()));
+        assert_eq!(("".to_string(), "ready".to_string()),
+                   r.read_until(&ReadUntil::String("ready".to_string())).expect("ready"));
+    }
+
+    #[test]
+    fn test_read_until_any() {
+        let f = io::Cursor::new("--More-- done");
+        let mut r = NBReader::new(f, None);
+        let needles = [ReadUntil::String("--More--".to_string()),
+                       ReadUntil::String("done".to_string())];
+        assert_eq!((0, "".to_string(), "--More--".to_string()),
+                   r.read_until_any(&needles).expect("--More--"));
+        assert_eq!((1, " ".to_string(), "done".to_string()),
+                   r.read_until_any(&needles).expect("done"));
+    }
+
+    #[test]
+    fn test_inject() {
+        let f = io::Cursor::new("");
+        let mut r = NBReader::new(f, Some(100));
+        r.inject(b"injected line\r\n");
+        assert_eq!(("injected line".to_string(), "\r\n".to_string()),
+                   r.read_until(&ReadUntil::String("\r\n".to_string())).expect("injected"));
+        r.set_strip_escapes(true);
+        r.inject(b"\x1b[1mbold\x1b[0m\r\n");
+        assert_eq!("bold\r\n", r.buffered());
+    }
+
+    #[test]
+    fn test_tail_file() {
+        let mut log = tempfile::NamedTempFile::new().expect("cannot create log file");
+        log.write_all(b"old line\n").unwrap();
+        let tag = log.path().file_name().unwrap().to_string_lossy().into_owned();
+        // a source which stays open, so the reader doesn't hit EOF before the log line
+        let (read_fd, write_fd) = pipe();
+        let _writer = unsafe { File::from_raw_fd(write_fd) };
+        let mut r = NBReader::new(unsafe { File::from_raw_fd(read_fd) }, Some(2000));
+        r.tail_file(log.path()).expect("cannot tail log file");
+        log.write_all(b"server ready\n").unwrap();
+        log.flush().unwrap();
+        assert_eq!((format!("[{}] ", tag), "server ready\n".to_string()),
+                   r.read_until(&ReadUntil::String("server ready\n".to_string()))
+                       .expect("log line"));
+    }
+
+    #[test]
+    fn test_read_until_to() {
+        let f = io::Cursor::new("a lot of output\n>>> rest");
+        let mut r = NBReader::new(f, Some(1000));
+        let mut out = Vec::new();
+        assert_eq!(Capture::Streamed(16), r.read_until_to(">>> ", 10, &mut out).expect("prompt"));
+        assert_eq!(b"a lot of output\n".to_vec(), out);
+        assert_eq!(("".to_string(), "rest".to_string()),
+                   r.read_until(&ReadUntil::EOF).expect("rest"));
+
+        // within the threshold nothing is written
+        let f = io::Cursor::new("short\n>>> ");
+        let mut r = NBReader::new(f, Some(1000));
+        let mut out = Vec::new();
+        assert_eq!(Capture::Collected("short\n".to_string()),
+                   r.read_until_to(">>> ", 10, &mut out).expect("prompt"));
+        assert!(out.is_empty());
+
+        // the fail pattern is found although the line is split up while reading
+        let (read_fd, write_fd) = pipe();
+        let mut writer = unsafe { File::from_raw_fd(write_fd) };
+        let mut r = NBReader::new(unsafe { File::from_raw_fd(read_fd) }, Some(1000));
+        r.add_fail_pattern(ReadUntil::String("Error".to_string()));
+        writer.write_all(b"line 1\nline 2: Er").unwrap();
+        thread::sleep(time::Duration::from_millis(200));
+        writer.write_all(b"ror\n>>> ").unwrap();
+        let mut out = Vec::new();
+        match r.read_until_to(">>> ", 0, &mut out) {
+            Err(Error(ErrorKind::FailPattern(_, _, got), _)) => assert_eq!("line 2: Error", got),
+            res => panic!("expected the fail pattern, got {:?}", res),
+        }
+        assert_eq!(b"line 1\n".to_vec(), out);
+    }
+
+    #[test]
+    fn test_read_until_idle() {
+        let f = io::Cursor::new("everything at once");
+        let mut r = NBReader::new(f, Some(1000));
+        assert_eq!("everything at once",
+                   r.read_until_idle(time::Duration::from_millis(50)).expect("idle"));
+        assert_eq!("", r.read_until_idle(time::Duration::from_millis(50)).expect("idle"));
+    }
+
     #[test]
     fn test_try_read() {
         let f = io::Cursor::new("lorem");
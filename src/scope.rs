@@ -0,0 +1,158 @@
+//! Tie sessions to a scope which cancels and shuts them all down when it ends
+//!
+//! Sessions used from several threads (e.g. with `std::thread::scope`) are registered with
+//! a `SessionScope`. Dropping the scope aborts every `exp_*` call still waiting and ends
+//! all processes together within one grace period, so a failing test can't leave
+//! blocked threads or running processes behind.
+
+use crate::process::{signal, wait, WaitHandle};
+use crate::reader::CancelHandle;
+use crate::session::{spawn_command, PtySession};
+use std::process::Command;
+use std::sync::Mutex;
+use std::{thread, time};
+use crate::errors::*; // load error-chain
+
+/// Owner of the sessions started with `spawn` (or registered with `register`)
+///
+/// On `shutdown` (or drop) all waiting `exp_*` calls of the sessions fail with
+/// `ErrorKind::Cancelled` (or `ErrorKind::EOF` if they see the process exit first), all
+/// processes get SIGTERM at once and the ones which are still running after the grace
+/// period get SIGKILL. Processes which were waited for already get no signal. The scope
+/// waits for the processes, `wait` and `exit_status` of the sessions return the status
+/// afterwards.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::scope::SessionScope;
+/// use std::process::Command;
+/// use std::thread;
+/// use std::time::Duration;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let scope = SessionScope::new(Duration::from_millis(500));
+/// let mut server = scope.spawn(Command::new("cat"), Some(30000))?;
+/// let waiting = thread::spawn(move || server.exp_string("never printed"));
+/// drop(scope);
+/// assert!(waiting.join().unwrap().is_err());
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub struct SessionScope {
+    grace: time::Duration,
+    members: Mutex<Vec<(CancelHandle, WaitHandle)>>,
+}
+
+impl SessionScope {
+    /// Create an empty scope which gives the processes `grace` to exit on shutdown
+    pub fn new(grace: time::Duration) -> Self {
+        SessionScope { grace, members: Mutex::new(Vec::new()) }
+    }
+
+    /// Same as `session::spawn_command`, with the session registered in the scope
+    pub fn spawn(&self, command: Command, timeout_ms: Option<u64>) -> Result<PtySession> {
+        let session = spawn_command(command, timeout_ms)?;
+        self.register(&session);
+        Ok(session)
+    }
+
+    /// Shut down `session` together with the scope
+    pub fn register(&self, session: &PtySession) {
+        self.members
+            .lock()
+            .unwrap()
+            .push((session.cancel_handle(), session.process.wait_handle()));
+    }
+
+    /// Cancel all waiting `exp_*` calls and end all processes, waiting at most the grace
+    /// period before killing the rest. Returns how many had to be killed.
+    pub fn shutdown(&self) -> usize {
+        let members = std::mem::take(&mut *self.members.lock().unwrap());
+        for (cancel, process) in &members {
+            cancel.cancel();
+            let _ = process.signal(signal::SIGTERM);
+        }
+        let deadline = time::Instant::now() + self.grace;
+        let mut running: Vec<WaitHandle> =
+            members.into_iter().map(|(_, process)| process).collect();
+        loop {
+            running.retain(|process| {
+                match process.status() {
+                    Some(wait::WaitStatus::StillAlive) => true,
+                    // exited, or already waited for by its session
+                    _ => false,
+                }
+            });
+            if running.is_empty() || time::Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+        for process in &running {
+            let _ = process.signal(signal::SIGKILL);
+            let _ = process.wait();
+        }
+        running.len()
+    }
+}
+
+impl Drop for SessionScope {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shutdown() {
+        || -> Result<()> {
+            let scope = SessionScope::new(time::Duration::from_millis(300));
+            let mut waiting = scope.spawn(Command::new("cat"), Some(30000))?;
+            let mut stubborn = Command::new("bash");
+            stubborn.args(["-c", "trap '' TERM; echo ready; while :; do sleep 0.05; done"]);
+            let mut stubborn = scope.spawn(stubborn, Some(1000))?;
+            stubborn.exp_string("ready")?;
+
+            let start = time::Instant::now();
+            let handle = thread::spawn(move || waiting.exp_string("never printed"));
+            thread::sleep(time::Duration::from_millis(100));
+            assert_eq!(1, scope.shutdown());
+            let elapsed = start.elapsed();
+            assert!(elapsed < time::Duration::from_secs(2), "took {:?}", elapsed);
+            // depending on what the thread sees first
+            match handle.join().unwrap() {
+                Err(Error(ErrorKind::Cancelled(..), _)) | Err(Error(ErrorKind::EOF(..), _)) => {}
+                r => panic!("expected cancellation, got {:?}", r),
+            }
+            assert_eq!(0, scope.shutdown());
+            // the scope waited for it, the session still knows how it ended
+            assert_eq!(Some(wait::WaitStatus::Signaled(stubborn.process.child_pid,
+                                                       signal::SIGKILL, false)),
+                       stubborn.process.status());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_shutdown failed: {}", e));
+    }
+
+    #[test]
+    fn test_shutdown_after_wait() {
+        || -> Result<()> {
+            let scope = SessionScope::new(time::Duration::from_millis(300));
+            let session = scope.spawn(Command::new("true"), Some(1000))?;
+            let status = session.process.wait()?;
+            // its pid may belong to another process by now
+            assert!(!session.process.wait_handle().signal(signal::SIGTERM)?);
+            assert_eq!(0, scope.shutdown());
+            assert_eq!(status, session.process.wait()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_shutdown_after_wait failed: {}", e));
+    }
+}
@@ -1,23 +1,182 @@
 //! Main module of rexpect: start new process and interact with it
 
-use crate::process::PtyProcess;
-use crate::reader::{NBReader, Regex};
+use crate::ansi::Color;
+use crate::control_code::ControlCode;
+use crate::key::Key;
+use crate::process::{wait, BackendKind, PtyProcess, SpawnOptions};
+use crate::reader::{self, CancelHandle, Capture, Consume, NBReader, ReaderSnapshot, Regex};
 pub use crate::reader::ReadUntil;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hasher};
+use std::{fmt, thread, time};
 use std::fs::File;
-use std::io::LineWriter;
+use std::io::{self, LineWriter};
 use std::process::Command;
 use std::io::prelude::*;
 use std::ops::{Deref, DerefMut};
+use std::os::unix::io::{AsRawFd, RawFd};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::termios;
 use crate::errors::*; // load error-chain
 use tempfile;
 
+/// What `send_line` appends to the line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineTerminator {
+    /// `\n`, what shells on Unix expect
+    #[default]
+    Lf,
+    /// `\r`, what the enter key sends, e.g. for Windows console programs
+    Cr,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineTerminator {
+    pub fn as_bytes(self) -> &'static [u8] {
+        match self {
+            LineTerminator::Lf => b"\n",
+            LineTerminator::Cr => b"\r",
+            LineTerminator::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// When input sent with `send` is flushed to the process, see `PtySession::set_flush_policy`.
+///
+/// Independent of the policy `send_line`, `send_control` and `send_eof` always flush,
+/// and all pending input is flushed before waiting for output (`exp_*`, `read_line`, ...),
+/// so the process never waits for input which is stuck in our buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FlushPolicy {
+    /// flush whenever a newline is sent (the default)
+    #[default]
+    Line,
+    /// flush after every `send`
+    Always,
+    /// flush when the last flush is at least this long ago, so many small `send`s
+    /// are sent together
+    Interval(time::Duration),
+}
+
+/// What `send_line` does about the line being echoed back by the terminal,
+/// see `PtySession::set_echo_check`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EchoCheck {
+    /// return right away (the default)
+    #[default]
+    Off,
+    /// wait until the echoed line shows up, it stays in the output
+    Wait,
+    /// wait until the echoed line shows up and remove it from the output
+    Strip,
+}
+
+/// How much of the transcript is kept in memory, see
+/// `PtySession::set_transcript_retention`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TranscriptRetention {
+    /// everything (the default)
+    #[default]
+    All,
+    /// only the output consumed by the last `n` matches (one per `exp_*`, `read_line` or
+    /// `read_until_idle` call), older output is dropped, the transcript only tells how
+    /// much
+    LastMatches(usize),
+}
+
+// the output recorded since `start_transcript`
+#[derive(Debug, Default)]
+struct Transcript {
+    // the consumed output, one entry per match
+    matches: VecDeque<String>,
+    // number of chars dropped by the retention
+    dropped: usize,
+}
+
+impl Transcript {
+    fn retain(&mut self, retention: TranscriptRetention) {
+        if let TranscriptRetention::LastMatches(n) = retention {
+            while self.matches.len() > n {
+                self.dropped += self.matches.pop_front().map_or(0, |m| m.chars().count());
+            }
+        }
+    }
+}
+
+// passes streamed output on to the writer and keeps a copy for the transcript
+struct Tee<'a, W> {
+    writer: &'a mut W,
+    copy: Option<String>,
+}
+
+impl<'a, W: Write> Write for Tee<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        if let Some(ref mut copy) = self.copy {
+            copy.push_str(&reader::from_bytes(&buf[..written]));
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// How `PtySession::interrupt` delivers Ctrl-C
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    /// send the interrupt character of the terminal (usually Ctrl-C), which the terminal
+    /// turns into SIGINT unless the program switched it into raw mode
+    Key,
+    /// send SIGINT to the foreground process group, see `PtyProcess::interrupt`
+    Signal,
+}
+
+/// Something which happened in a session, see `PtySession::forward_events`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    /// an `exp_*` call found its needle: the output before it and the match
+    Matched { before: String, matched: String },
+    /// a fail pattern (see `add_fail_pattern`) showed up in the output
+    FailPattern { pattern: String, got: String },
+    /// an `exp_*` call failed for another reason, with the error message
+    Failed(String),
+    /// the output ended. Carries the exit status if it was collected for an `EOF` error,
+    /// a successful `exp_eof` leaves it to `process.wait()`.
+    Exited(Option<wait::WaitStatus>),
+}
+
+/// Handler which is called when an `expect_before`/`expect_after` pattern matched
+type PatternHandler = Box<dyn FnMut(&mut PtySession) -> Result<()> + Send>;
+
 /// Interact with a process with read/write/signals, etc.
 #[allow(dead_code)]
 pub struct PtySession {
+    // first, so the process is gone before the writer flushes on drop: a flush to a
+    // process which doesn't read could block forever
     pub process: PtyProcess,
     pub writer: LineWriter<File>,
     pub reader: NBReader,
     pub commandname: String, // only for debugging purposes now
+    expect_before: Vec<(ReadUntil, PatternHandler)>,
+    expect_after: Vec<(ReadUntil, PatternHandler)>,
+    line_terminator: LineTerminator,
+    // sent with `send_secret`, masked in everything we report
+    secrets: Vec<String>,
+    // see `set_prompt`
+    prompt: Option<Regex>,
+    echo_check: EchoCheck,
+    flush_policy: FlushPolicy,
+    last_flush: time::Instant,
+    // all consumed output, see `start_transcript`
+    transcript: Option<Transcript>,
+    transcript_retention: TranscriptRetention,
+    // see `forward_events`
+    event_sink: Option<Box<dyn FnMut(SessionEvent) + Send>>,
 }
 
 /// Start a process in a tty session, write and read from it
@@ -40,44 +199,199 @@ pub struct PtySession {
 /// # }
 /// ```
 impl PtySession {
-    /// sends string and a newline to process
+    /// sends string and a newline (see `set_line_terminator`) to process
     ///
     /// this is guaranteed to be flushed to the process
     /// returns number of written bytes
     pub fn send_line(&mut self, line: &str) -> Result<usize> {
         let mut len = self.send(line)?;
         len += self.writer
-            .write(&['\n' as u8])
+            .write(self.line_terminator.as_bytes())
             .chain_err(|| "cannot write newline")?;
+        // the LineWriter only flushes by itself on `\n`
+        self.flush()?;
+        match self.echo_check {
+            EchoCheck::Off => {}
+            EchoCheck::Wait => {
+                self.reader
+                    .wait_until(&ReadUntil::String(line.to_string()))
+                    .map_err(|e| self.redact_error(e))?;
+            }
+            EchoCheck::Strip => {
+                // the terminal echoes the line terminator as \r\n
+                let echo = Regex::new(&format!("{}\r*\n", regex::escape(line)))
+                    .chain_err(|| "line too long to check its echo")?;
+                let (start, end) = self.reader
+                    .wait_until(&ReadUntil::Regex(echo))
+                    .map_err(|e| self.redact_error(e))?;
+                self.reader.discard(start..end);
+            }
+        }
         Ok(len)
     }
 
+    /// Make `send_line` wait until the terminal echoed the line back before returning
+    /// (`EchoCheck::Off` by default). Without that the next `exp_*` call could match
+    /// within the echo instead of the output of the process.
+    ///
+    /// Only makes sense if the terminal echoes, i.e. not directly after spawning
+    /// (echo is switched off at spawn, see `PtyProcess::set_echo`) but e.g. within bash
+    /// or over ssh.
+    pub fn set_echo_check(&mut self, check: EchoCheck) {
+        self.echo_check = check;
+    }
+
+    /// Set what `send_line` appends to the line, `LineTerminator::Lf` by default
+    pub fn set_line_terminator(&mut self, terminator: LineTerminator) {
+        self.line_terminator = terminator;
+    }
+
 
-    /// Send string to process. As stdin of the process is most likely buffered, you'd
-    /// need to call `flush()` after `send()` to make the process actually see your input.
+    /// Send string to process. The input is buffered, when it's flushed to the process
+    /// depends on the flush policy (see `set_flush_policy`), call `flush()` to make the
+    /// process see your input right away.
     ///
     /// Returns number of written bytes
     pub fn send(&mut self, s: &str) -> Result<usize> {
         self.writer
-            .write(s.as_bytes())
-            .chain_err(|| "cannot write line to process")
+            .write_all(s.as_bytes())
+            .chain_err(|| "cannot write line to process")?;
+        match self.flush_policy {
+            FlushPolicy::Line => {}
+            FlushPolicy::Always => self.flush()?,
+            FlushPolicy::Interval(interval) => {
+                if self.last_flush.elapsed() >= interval {
+                    self.flush()?;
+                }
+            }
+        }
+        Ok(s.len())
+    }
+
+    /// Set when input sent with `send` is flushed, `FlushPolicy::Line` by default
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.flush_policy = policy;
+    }
+
+    /// Send raw bytes to the process, e.g. for binary protocols of bootloaders or flashing
+    /// tools. No UTF-8 is required and the bytes are flushed right away.
+    ///
+    /// Note that the terminal still processes the input unless you switch it into raw
+    /// mode (e.g. `stty raw` or the program does it): Ctrl-C (`0x03`) sends SIGINT, etc.
+    ///
+    /// Returns number of written bytes
+    pub fn send_bytes(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.writer
+            .write_all(bytes)
+            .chain_err(|| "cannot write bytes to process")?;
+        self.flush()?;
+        Ok(bytes.len())
+    }
+
+    /// Send a key press, e.g. `Key::Up` or `Key::F1`, and flush. Doesn't allocate, so it
+    /// can be used to pump thousands of keys per second into a full screen program.
+    pub fn send_key<K: Into<Key>>(&mut self, key: K) -> Result<()> {
+        let mut buf = [0u8; 4];
+        self.writer
+            .write_all(key.into().encode(&mut buf))
+            .chain_err(|| "cannot send key")?;
+        self.flush()
+    }
+
+    /// Send several key presses with one flush at the end, see `send_key`
+    pub fn send_keys(&mut self, keys: &[Key]) -> Result<()> {
+        let mut buf = [0u8; 4];
+        for key in keys {
+            self.writer
+                .write_all(key.encode(&mut buf))
+                .chain_err(|| "cannot send key")?;
+        }
+        self.flush()
+    }
+
+    /// Same as `send_line`, but `secret` is masked as `********` in everything this crate
+    /// reports from now on, e.g. the output within timeout errors (in case the process
+    /// echoes it back).
+    pub fn send_secret(&mut self, secret: &str) -> Result<usize> {
+        if !secret.is_empty() && !self.secrets.iter().any(|s| s == secret) {
+            self.secrets.push(secret.to_string());
+        }
+        self.send_line(secret)
+    }
+
+    /// Wait for the password prompt `prompt_regex` (e.g. `"[Pp]assword: ?$"`) and answer
+    /// it with `secret`, see `send_secret`. Handy for sudo or ssh.
+    pub fn answer_password(&mut self, prompt_regex: &str, secret: &str) -> Result<()> {
+        self.exp_regex(prompt_regex)?;
+        self.send_secret(secret)?;
+        Ok(())
+    }
+
+    /// Mask all secrets sent with `send_secret` within `s` (also in their escaped form,
+    /// as found in `Debug` output)
+    fn redact(&self, s: &str) -> String {
+        self.secrets.iter().fold(s.to_string(), |s, secret| {
+            s.replace(secret.as_str(), "********")
+                .replace(&secret.escape_debug().to_string(), "********")
+        })
+    }
+
+    // mask the secrets within the output contained in errors
+    fn redact_error(&self, e: Error) -> Error {
+        if self.secrets.is_empty() {
+            return e;
+        }
+        match e {
+            Error(ErrorKind::Timeout(expected, got, timeout), state) => {
+                Error(ErrorKind::Timeout(expected, self.redact(&got), timeout), state)
+            }
+            Error(ErrorKind::EOF(expected, got, status), state) => {
+                Error(ErrorKind::EOF(expected, self.redact(&got), status), state)
+            }
+            Error(ErrorKind::FailPattern(expected, pattern, got), state) => {
+                Error(ErrorKind::FailPattern(expected, pattern, self.redact(&got)), state)
+            }
+            Error(ErrorKind::Cancelled(expected, got), state) => {
+                Error(ErrorKind::Cancelled(expected, self.redact(&got)), state)
+            }
+            e => e,
+        }
+    }
+
+    /// Send string char by char, waiting `delay` plus a random duration of up to `jitter`
+    /// after each char, like a human typing. Some curses/readline programs drop input
+    /// which arrives faster than they poll.
+    ///
+    /// Every char is flushed right away. Returns number of written bytes
+    pub fn send_slow(&mut self, s: &str, delay: time::Duration, jitter: time::Duration)
+                     -> Result<usize> {
+        // xorshift seeded by std's random hasher keys, good enough for jitter
+        let mut rng = RandomState::new().build_hasher().finish() | 1;
+        let jitter_nanos = jitter.as_nanos() as u64;
+        let mut buf = [0u8; 4];
+        let mut len = 0;
+        for c in s.chars() {
+            len += self.send(c.encode_utf8(&mut buf))?;
+            self.flush()?;
+            rng ^= rng << 13;
+            rng ^= rng >> 7;
+            rng ^= rng << 17;
+            let extra = if jitter_nanos > 0 { rng % (jitter_nanos + 1) } else { 0 };
+            thread::sleep(delay + time::Duration::from_nanos(extra));
+        }
+        Ok(len)
     }
 
     /// Send a control code to the running process and consume resulting output line
     /// (which is empty because echo is off)
     ///
-    /// E.g. `send_control('c')` sends ctrl-c. Upper/smaller case does not matter.
-    pub fn send_control(&mut self, c: char) -> Result<()> {
-        let code = match c {
-            'a'..='z' => c as u8 + 1 - 'a' as u8,
-            'A'..='Z' => c as u8 + 1 - 'A' as u8,
-            '[' => 27,
-            '\\' => 28,
-            ']' => 29,
-            '^' => 30,
-            '_' => 31,
-            _ => return Err(format!("I don't understand Ctrl-{}", c).into()),
-        };
+    /// E.g. `send_control('c')` or `send_control(ControlCode::EndOfText)` sends ctrl-c.
+    /// Upper/smaller case does not matter.
+    pub fn send_control<C>(&mut self, c: C) -> Result<()>
+        where C: TryInto<ControlCode>,
+              Error: From<C::Error>
+    {
+        let code: u8 = c.try_into()?.into();
         self.writer
             .write_all(&[code])
             .chain_err(|| "cannot send control")?;
@@ -88,80 +402,214 @@ impl PtySession {
         Ok(())
     }
 
-    // wrapper around reader::read_until to give more context for errors
-    fn exp(&mut self, needle: &ReadUntil) -> Result<(String, String)> {
-        match self.reader.read_until(needle) {
-            Ok(s) => Ok(s),
-            Err(Error(ErrorKind::EOF(expected, got, _), _)) => {
-                Err(ErrorKind::EOF(expected, got, self.process.status()).into())
+    /// Interrupt the running program like pressing Ctrl-C does. Programs reading the
+    /// keyboard in raw mode (editors, some REPLs) only see a key with `Interrupt::Key`,
+    /// use `Interrupt::Signal` to interrupt them anyway.
+    pub fn interrupt(&mut self, how: Interrupt) -> Result<()> {
+        self.flush()?;
+        match how {
+            Interrupt::Key => {
+                let intr = termios::tcgetattr(self.process.pty.as_raw_fd())
+                    .map(|t| t.control_chars[termios::SpecialCharacterIndices::VINTR as usize])
+                    .unwrap_or(ControlCode::EndOfText.into());
+                self.writer
+                    .write_all(&[intr])
+                    .chain_err(|| "cannot send interrupt")?;
+                self.writer.flush().chain_err(|| "cannot flush after sending interrupt")
             }
-            Err(e) => Err(e),
+            Interrupt::Signal => self.process.interrupt(),
         }
     }
 
-    /// Make sure all bytes written via `send()` are sent to the process
-    pub fn flush(&mut self) -> Result<()> {
-        self.writer.flush().chain_err(|| "could not flush")
+    /// Signal end of input to the process (like pressing Ctrl-D in a terminal), so programs
+    /// reading stdin until EOF finish up. The output of the process can still be read
+    /// afterwards.
+    ///
+    /// A pty can't be closed for writing only, instead this sends the EOF character of
+    /// the terminal (usually Ctrl-D). Note that it only means EOF at the start of a line,
+    /// so everything sent before should be terminated with a newline.
+    pub fn send_eof(&mut self) -> Result<()> {
+        let eof = termios::tcgetattr(self.process.pty.as_raw_fd())
+            .map(|t| t.control_chars[termios::SpecialCharacterIndices::VEOF as usize])
+            .unwrap_or(ControlCode::EndOfTransmission.into());
+        self.flush()?;
+        self.writer
+            .write_all(&[eof])
+            .chain_err(|| "cannot send EOF")?;
+        self.flush()
     }
 
-    /// Read one line (blocking!) and return line without the newline
-    /// (waits until \n is in the output fetches the line and removes \r at the end if present)
-    pub fn read_line(&mut self) -> Result<String> {
-        match self.exp(&ReadUntil::String('\n'.to_string())) {
-            Ok((mut line, _)) => {
-                if line.ends_with('\r') {
-                    line.pop().expect("this never happens");
+    // wrapper around reader::read_until to give more context for errors
+    // and to run the expect_before/expect_after handlers
+    fn exp(&mut self, needle: &ReadUntil) -> Result<(String, String)> {
+        self.exp_with(needle, Consume::All)
+    }
+
+    /// Same as `exp_any` with a single needle, but `consume` decides which part of the
+    /// output is removed when the needle matches (see `Consume`), so several checks can
+    /// look at overlapping output. Output consumed by `expect_before`/`expect_after`
+    /// handlers is removed in any case.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::{spawn, ReadUntil};
+    /// use rexpect::reader::Consume;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut s = spawn("cat", Some(1000))?;
+    /// s.send_line("error: disk full")?;
+    /// s.exp_with(&ReadUntil::String("error".into()), Consume::UntilMatch)?;
+    /// assert_eq!("error: disk full", s.read_line()?);
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn exp_with(&mut self, needle: &ReadUntil, consume: Consume) -> Result<(String, String)> {
+        // the process could be waiting for the input we still hold back
+        self.flush()?;
+        // output before patterns which were handled by expect_before/expect_after
+        let mut skipped = String::new();
+        loop {
+            let before = self.expect_before.len();
+            let needles: Vec<ReadUntil> = self.expect_before
+                .iter()
+                .map(|(pattern, _)| pattern.clone())
+                .chain(std::iter::once(needle.clone()))
+                .chain(self.expect_after.iter().map(|(pattern, _)| pattern.clone()))
+                .collect();
+            match self.reader.read_until_any(&needles) {
+                Ok((i, first, second)) if i == before => {
+                    self.reader.unread(&first, &second, consume);
+                    match consume {
+                        Consume::All => self.record(&first, &second),
+                        Consume::UntilMatch => self.record(&first, ""),
+                        Consume::Nothing => {}
+                    }
+                    self.emit(|s| SessionEvent::Matched {
+                        before: s.redact(&first),
+                        matched: s.redact(&second),
+                    });
+                    if let ReadUntil::EOF = *needle {
+                        self.emit(|_| SessionEvent::Exited(None));
+                    }
+                    skipped.push_str(&first);
+                    return Ok((skipped, second));
                 }
-                Ok(line)
+                Ok((i, first, second)) => {
+                    self.record(&first, &second);
+                    skipped.push_str(&first);
+                    if i < before {
+                        self.run_pattern_handler(true, i)?
+                    } else {
+                        self.run_pattern_handler(false, i - before - 1)?
+                    }
+                }
+                Err(e) => return Err(self.exp_error(e)),
             }
-            Err(e) => Err(e),
         }
     }
 
-    /// Return `Some(c)` if a char is ready in the stdout stream of the process, return `None`
-    /// otherwise. This is nonblocking.
-    pub fn try_read(&mut self) -> Option<char> {
-        self.reader.try_read()
+    // one round of `exp` which doesn't block (and doesn't run the expect_before/after
+    // handlers), `None` if the needle isn't there yet
+    fn try_exp(&mut self, needle: &ReadUntil) -> Result<Option<(String, String)>> {
+        self.flush()?;
+        match self.reader.try_read_until(needle) {
+            Ok(Some((first, second))) => {
+                self.record(&first, &second);
+                Ok(Some((first, second)))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(self.exp_error(e)),
+        }
     }
 
-    /// Wait until we see EOF (i.e. child process has terminated)
-    /// Return all the yet unread output
-    pub fn exp_eof(&mut self) -> Result<String> {
-        self.exp(&ReadUntil::EOF).and_then(|(_, s)| Ok(s))
+    // add the exit status to EOF errors and mask the secrets
+    fn exp_error(&mut self, e: Error) -> Error {
+        let e = match e {
+            Error(ErrorKind::EOF(expected, got, _), _) => {
+                let status = self.process.status();
+                self.emit(|_| match status {
+                    Some(wait::WaitStatus::StillAlive) => SessionEvent::Exited(None),
+                    status => SessionEvent::Exited(status),
+                });
+                let e = ErrorKind::EOF(expected, got, status).into();
+                self.redact_error(e)
+            }
+            e => self.redact_error(e),
+        };
+        self.emit(|_| match e {
+            Error(ErrorKind::FailPattern(_, ref pattern, ref got), _) => {
+                SessionEvent::FailPattern { pattern: pattern.clone(), got: got.clone() }
+            }
+            ref e => SessionEvent::Failed(e.to_string()),
+        });
+        e
     }
 
-    /// Wait until provided regex is seen on stdout of child process.
-    /// Return a tuple:
-    /// 1. the yet unread output
-    /// 2. the matched regex
+    /// Send everything which happens in the session (see `SessionEvent`) to `sink`, e.g.
+    /// the sending side of a channel, so other threads can follow the session. Secrets
+    /// are masked. Replaces the sink set before.
     ///
-    /// Note that `exp_regex("^foo")` matches the start of the yet consumed output.
-    /// For matching the start of the line use `exp_regex("\nfoo")`
-    pub fn exp_regex(&mut self, regex: &str) -> Result<(String, String)> {
-        let res = self.exp(&ReadUntil::Regex(Regex::new(regex).chain_err(|| "invalid regex")?))
-            .and_then(|s| Ok(s));
-        res
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn;
+    /// use rexpect::session::SessionEvent;
+    /// use std::sync::mpsc::channel;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let (tx, rx) = channel();
+    /// let mut p = spawn("cat", Some(1000))?;
+    /// p.forward_events(move |event| { let _ = tx.send(event); });
+    /// p.send_line("hello")?;
+    /// p.exp_string("hello")?;
+    /// assert_eq!(SessionEvent::Matched { before: "".into(), matched: "hello".into() },
+    ///            rx.recv().unwrap());
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn forward_events<F>(&mut self, sink: F)
+        where F: FnMut(SessionEvent) + Send + 'static
+    {
+        self.event_sink = Some(Box::new(sink));
     }
 
-    /// Wait until provided string is seen on stdout of child process.
-    /// Return the yet unread output (without the matched string)
-    pub fn exp_string(&mut self, needle: &str) -> Result<String> {
-        self.exp(&ReadUntil::String(needle.to_string()))
-            .and_then(|(s, _)| Ok(s))
+    // the event is only created if there's a sink
+    fn emit<F: FnOnce(&Self) -> SessionEvent>(&mut self, event: F) {
+        if self.event_sink.is_some() {
+            let event = event(self);
+            if let Some(ref mut sink) = self.event_sink {
+                sink(event);
+            }
+        }
     }
 
-    /// Wait until provided char is seen on stdout of child process.
-    /// Return the yet unread output (without the matched char)
-    pub fn exp_char(&mut self, needle: char) -> Result<String> {
-        self.exp(&ReadUntil::String(needle.to_string()))
-            .and_then(|(s, _)| Ok(s))
+    fn run_pattern_handler(&mut self, before: bool, index: usize) -> Result<()> {
+        let mut handlers = if before {
+            std::mem::take(&mut self.expect_before)
+        } else {
+            std::mem::take(&mut self.expect_after)
+        };
+        let res = (handlers[index].1)(self);
+        // keep handlers which were registered from within the handler
+        let list = if before { &mut self.expect_before } else { &mut self.expect_after };
+        handlers.append(list);
+        *list = handlers;
+        res
     }
 
-    /// Wait until any of the provided needles is found.
+    /// Register a pattern which is checked *before* the needle of every following
+    /// `exp_*` call (just like `expect_before` in TCL expect). When it matches, the matched
+    /// text is removed from the output, `handler` is called and waiting for the needle
+    /// continues (with a fresh timeout).
     ///
-    /// Return a tuple with:
-    /// 1. the yet unread string, without the matching needle (empty in case of EOF and NBytes)
-    /// 2. the matched string
+    /// Useful to answer prompts which can show up at any time, e.g. pagination.
     ///
     /// # Example:
     ///
@@ -172,281 +620,1975 @@ impl PtySession {
     /// # fn main() {
     ///     # || -> Result<()> {
     /// let mut s = spawn("cat", Some(1000))?;
-    /// s.send_line("hello, polly!")?;
-    /// s.exp_any(vec![ReadUntil::String("hello".into()),
-    ///                ReadUntil::EOF])?;
+    /// s.expect_before(ReadUntil::String("--More--".into()), |s| {
+    ///     s.send_line("next page")?;
+    ///     Ok(())
+    /// });
+    /// s.send_line("--More--")?;
+    /// s.exp_string("next page")?;
     ///         # Ok(())
     ///     # }().expect("test failed");
     /// # }
     /// ```
-    pub fn exp_any(&mut self, needles: Vec<ReadUntil>) -> Result<(String, String)> {
-        self.exp(&ReadUntil::Any(needles))
+    pub fn expect_before<F>(&mut self, pattern: ReadUntil, handler: F)
+        where F: FnMut(&mut PtySession) -> Result<()> + Send + 'static
+    {
+        self.expect_before.push((pattern, Box::new(handler)));
     }
-}
 
-/// Turn e.g. "prog arg1 arg2" into ["prog", "arg1", "arg2"]
-/// Also takes care of single and double quotes
-fn tokenize_command(program: &str) -> Vec<String> {
-    let re = Regex::new(r#""[^"]+"|'[^']+'|[^'" ]+"#).unwrap();
-    let mut res = vec![];
-    for cap in re.captures_iter(program) {
-        res.push(cap[0].to_string());
+    /// Same as `expect_before`, but the pattern is only checked if the needle of the
+    /// `exp_*` call didn't match
+    pub fn expect_after<F>(&mut self, pattern: ReadUntil, handler: F)
+        where F: FnMut(&mut PtySession) -> Result<()> + Send + 'static
+    {
+        self.expect_after.push((pattern, Box::new(handler)));
     }
-    res
-}
-
-/// Start command in background in a pty session (pty fork) and return a struct
-/// with writer and buffered reader (for unblocking reads).
-///
-/// #Arguments:
-///
-/// - `program`: This is split at spaces and turned into a `process::Command`
-///   if you wish more control over this, use `spawn_command`
-/// - `timeout`: If Some: all `exp_*` commands time out after x millisecons, if None: never times
-///   out.
-///   It's higly recommended to put a timeout there, as otherwise in case of
-///   a problem the program just hangs instead of exiting with an
-///   error message indicating where it stopped.
-///   For automation 30'000 (30s, the default in pexpect) is a good value.
-pub fn spawn(program: &str, timeout_ms: Option<u64>) -> Result<PtySession> {
-    let command = if program.find(" ").is_some() {
-        let mut parts = tokenize_command(program);
-        let mut cmd = Command::new(&parts[0].to_string());
-        parts.remove(0);
-        cmd.args(parts);
-        cmd
-    } else {
-        Command::new(program)
-    };
-    spawn_command(command, timeout_ms)
-}
-
-/// See `spawn`
-pub fn spawn_command(command: Command, timeout_ms: Option<u64>) -> Result<PtySession> {
-    let commandname = format!("{:?}", &command);
-    let mut process = PtyProcess::new(command)
-        .chain_err(|| "couldn't start process")?;
-    process.set_kill_timeout(timeout_ms);
-
-    let f = process.get_file_handle();
-    let writer = LineWriter::new(f.try_clone().chain_err(|| "couldn't open write stream")?);
-    let reader = NBReader::new(f, timeout_ms);
-    Ok(PtySession {
-           process: process,
-           writer: writer,
-           reader: reader,
-           commandname: commandname,
-       })
-}
-
-/// A repl session: e.g. bash or the python shell:
-/// You have a prompt where a user inputs commands and the shell
-/// executes it and writes some output
-pub struct PtyReplSession {
-    /// the prompt, used for `wait_for_prompt`, e.g. ">>> " for python
-    pub prompt: String,
-
-    /// the pty_session you prepared before (initiating the shell, maybe set a custom prompt, etc.)
-    /// see `spawn_bash` for an example
-    pub pty_session: PtySession,
-
-    /// if set, then the quit_command is called when this object is dropped
-    /// you need to provide this if the shell you're testing is not killed by just sending
-    /// SIGTERM
-    pub quit_command: Option<String>,
 
-    /// set this to true if the repl has echo on (i.e. sends user input to stdout)
-    /// although echo is set off at pty fork (see `PtyProcess::new`) a few repls still
-    /// seem to be able to send output. You may need to try with true first, and if
-    /// tests fail set this to false.
-    pub echo_on: bool,
-}
-
-impl PtyReplSession {
-    pub fn wait_for_prompt(&mut self) -> Result<String> {
-        self.pty_session.exp_string(&self.prompt)
+    /// Remove all patterns registered with `expect_before` and `expect_after`
+    pub fn clear_expect_handlers(&mut self) {
+        self.expect_before.clear();
+        self.expect_after.clear();
     }
 
-    /// Send cmd to repl and:
-    /// 1. wait for the cmd to be echoed (if `echo_on == true`)
-    /// 2. wait for the ready string being present
-    ///
-    /// Q: Why can't I just do `send_line` and immediately continue?
-    /// A: Executing a command in e.g. bash causes a fork. If the Unix kernel chooses the
-    ///    parent process (bash) to go first and the bash process sends e.g. Ctrl-C then the
-    ///    Ctrl-C goes to nirvana.
-    ///    The only way to prevent this situation is to wait for a ready string being present
-    ///    in the output.
-    ///
-    /// Another safe way to tackle this problem is to use `send_line()` and `wait_for_prompt()`
+    /// A handle which can be sent to another thread to abort a blocking `exp_*`,
+    /// `read_line` or `read_until_idle` call right away, which then fails with
+    /// `ErrorKind::Cancelled` instead of waiting for the timeout.
     ///
     /// # Example:
     ///
     /// ```
-    /// use rexpect::spawn_bash;
-    /// # use rexpect::errors::*;
+    /// use rexpect::spawn;
+    /// use rexpect::errors::*;
+    /// use std::{thread, time};
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn("cat", Some(60_000))?;
+    /// let handle = p.cancel_handle();
+    /// thread::spawn(move || {
+    ///     thread::sleep(time::Duration::from_millis(100));
+    ///     handle.cancel();
+    /// });
+    /// match p.exp_string("never") {
+    ///     Err(Error(ErrorKind::Cancelled(..), _)) => {}
+    ///     r => panic!("expected cancel, got {:?}", r),
+    /// }
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.reader.cancel_handle()
+    }
+
+    /// Run `f` with `timeout` as the timeout of all `exp_*` calls (`None` waits forever),
+    /// the previous timeout is restored afterwards, even if `f` panics. Handy to give a
+    /// slow phase of the interaction more time.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::session::spawn_command;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut cmd = Command::new("bash");
+    /// cmd.args(["-c", "sleep 0.2; echo built; cat"]);
+    /// let mut p = spawn_command(cmd, Some(100))?;
+    /// p.with_timeout(Some(Duration::from_secs(5)), |p| p.exp_string("built"))?;
+    /// assert!(p.exp_string("never").is_err()); // after 100ms again
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn with_timeout<F, T>(&mut self, timeout: Option<time::Duration>, f: F) -> T
+        where F: FnOnce(&mut PtySession) -> T
+    {
+        let mut guard = self.timeout_guard(timeout);
+        f(&mut guard)
+    }
+
+    /// Set the timeout of all `exp_*` calls until the returned guard is dropped,
+    /// the guard derefs to the session. See `with_timeout`.
+    pub fn timeout_guard(&mut self, timeout: Option<time::Duration>) -> TimeoutGuard<'_> {
+        let guard = TimeoutGuard {
+            previous: self.reader.timeout(),
+            previous_deadline: self.reader.deadline(),
+            session: self,
+        };
+        guard.session.reader.set_timeout(timeout);
+        guard
+    }
+
+    /// Run `f` with all `exp_*` calls failing once `deadline` passed, so a sequence of
+    /// steps shares one time budget instead of each call getting the full timeout
+    /// (which still applies as well). The previous deadline is restored afterwards.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn;
+    /// use std::time::{Duration, Instant};
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn("cat", Some(30_000))?;
+    /// // the whole login must be done within 10s
+    /// p.with_deadline(Instant::now() + Duration::from_secs(10), |p| {
+    ///     p.send_line("login: polly")?;
+    ///     p.exp_string("login:")?;
+    ///     p.send_line("welcome")?;
+    ///     p.exp_string("welcome")
+    /// })?;
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn with_deadline<F, T>(&mut self, deadline: time::Instant, f: F) -> T
+        where F: FnOnce(&mut PtySession) -> T
+    {
+        let timeout = self.reader.timeout();
+        let mut guard = self.timeout_guard(timeout);
+        guard.reader.set_deadline(Some(deadline));
+        f(&mut guard)
+    }
+
+    /// Same as `exp_any` with a single needle, but fails once `deadline` passed (or the
+    /// timeout, whichever comes first). See `with_deadline` for a sequence of calls.
+    pub fn exp_until(&mut self, needle: &ReadUntil, deadline: time::Instant)
+                     -> Result<(String, String)> {
+        self.with_deadline(deadline, |s| s.exp(needle))
+    }
+
+    /// The kind of terminal the process runs in, see `BackendKind` for its capabilities
+    pub fn backend(&self) -> BackendKind {
+        self.process.backend()
+    }
+
+    /// Start recording all output consumed by `exp_*`, `read_line` and `read_until_idle`
+    /// calls (output fed into `hash_output_until` is not recorded), see `transcript`
+    pub fn start_transcript(&mut self) {
+        self.transcript.get_or_insert_with(Transcript::default);
+    }
+
+    /// Limit how much of the transcript is kept, so sessions running for days don't keep
+    /// all their output in memory. The last matches are still there for error reports.
+    pub fn set_transcript_retention(&mut self, retention: TranscriptRetention) {
+        self.transcript_retention = retention;
+        if let Some(ref mut transcript) = self.transcript {
+            transcript.retain(retention);
+        }
+    }
+
+    /// The output recorded since `start_transcript` followed by the output which was read
+    /// but not yet consumed, with secrets masked. `None` if no transcript is recorded.
+    ///
+    /// If output was dropped (see `set_transcript_retention`) the transcript starts with
+    /// a line telling how many bytes.
+    pub fn transcript(&self) -> Option<String> {
+        self.transcript.as_ref().map(|t| {
+            let mut res = String::new();
+            if t.dropped > 0 {
+                res.push_str(&format!("[{} bytes of earlier output dropped]\n", t.dropped));
+            }
+            for m in &t.matches {
+                res.push_str(m);
+            }
+            res.push_str(self.reader.buffered());
+            self.redact(&res)
+        })
+    }
+
+    /// Take a snapshot of the matcher state, to re-run `exp_*` calls from this point
+    /// against a recording later, see `Snapshot::replay`. E.g. to bisect a failing
+    /// session offline: record it with `start_transcript`, freeze at every step.
+    pub fn freeze(&mut self) -> Snapshot {
+        Snapshot {
+            reader: self.reader.freeze(),
+            position: self.transcript.as_ref().map(|t| {
+                t.dropped + t.matches.iter().map(|m| m.chars().count()).sum::<usize>()
+            }),
+        }
+    }
+
+    // add the output consumed by one match to the transcript
+    fn record(&mut self, before: &str, matched: &str) {
+        if let Some(ref mut transcript) = self.transcript {
+            if !before.is_empty() || !matched.is_empty() {
+                transcript.matches.push_back(format!("{}{}", before, matched));
+                transcript.retain(self.transcript_retention);
+            }
+        }
+    }
+
+    /// Make sure all bytes written via `send()` are sent to the process
+    pub fn flush(&mut self) -> Result<()> {
+        self.last_flush = time::Instant::now();
+        self.writer.flush().chain_err(|| "could not flush")
+    }
+
+    /// Read one line (blocking!) and return line without the newline
+    /// (waits until \n is in the output fetches the line and removes \r at the end if present)
+    pub fn read_line(&mut self) -> Result<String> {
+        match self.exp(&ReadUntil::String('\n'.to_string())) {
+            Ok((mut line, _)) => {
+                if line.ends_with('\r') {
+                    line.pop().expect("this never happens");
+                }
+                Ok(line)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Return `Some(c)` if a char is ready in the stdout stream of the process, return `None`
+    /// otherwise. This is nonblocking.
+    pub fn try_read(&mut self) -> Option<char> {
+        self.reader.try_read()
+    }
+
+    /// Append `data` to the read buffer as if the process had written it, e.g. to merge
+    /// output of other sources into the session or to test needles.
+    pub fn inject_output(&mut self, data: &[u8]) {
+        self.reader.inject(data);
+    }
+
+    /// Wait until the process didn't write anything for `idle` and return all the yet
+    /// unread output. Use this if there's no pattern telling that the process is done.
+    pub fn read_until_idle(&mut self, idle: std::time::Duration) -> Result<String> {
+        self.flush()?;
+        let output = self.reader.read_until_idle(idle)?;
+        self.record(&output, "");
+        Ok(output)
+    }
+
+    /// Feed all output up to `needle` into `digest` while it's read, instead of collecting
+    /// it in memory, and return the number of bytes hashed. The needle itself is consumed
+    /// but not hashed.
+    ///
+    /// Any `Write` works as digest, e.g. `sha2::Sha256` (the RustCrypto hashers implement
+    /// `Write`), so you can check that a dump of hundreds of MB matches a known checksum.
+    /// The timeout only triggers if the process doesn't write anything for that long.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn;
+    /// use std::io::{self, Write};
+    /// # use rexpect::errors::*;
+    ///
+    /// // a (very) simple checksum, use a real hash function in practice
+    /// struct Sum(u64);
+    /// impl Write for Sum {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         self.0 = buf.iter().fold(self.0, |sum, &b| sum + b as u64);
+    ///         Ok(buf.len())
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    /// }
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn("seq 1 10000", Some(1000))?;
+    /// let mut sum = Sum(0);
+    /// assert_eq!(58881, p.hash_output_until("9999\r\n", &mut sum)?);
+    /// p.exp_string("10000")?;
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn hash_output_until<D: Write>(&mut self, needle: &str, digest: &mut D)
+                                       -> Result<usize> {
+        self.flush()?;
+        match self.reader.read_until_to(needle, 0, digest) {
+            Ok(Capture::Streamed(written)) => Ok(written),
+            // only without any output before the needle
+            Ok(Capture::Collected(_)) => Ok(0),
+            Err(e) => Err(self.exp_error(e)),
+        }
+    }
+
+    /// Wait until `needle` shows up and return the output before it, unless that grows
+    /// beyond `threshold` bytes: then all of it is written into `writer` while it's read,
+    /// instead of collecting it in memory. See `PtyReplSession::execute_to_writer`.
+    ///
+    /// Fail patterns, the transcript, masking of secrets in errors and the timeout work
+    /// as with `exp_string`, except that the timeout restarts whenever there's new output.
+    /// With a transcript running the streamed output is recorded there as well.
+    pub fn exp_string_to_writer<W: Write>(&mut self, needle: &str, threshold: usize,
+                                          writer: &mut W) -> Result<Capture> {
+        self.flush()?;
+        let mut tee = Tee {
+            writer,
+            copy: self.transcript.as_ref().map(|_| String::new()),
+        };
+        match self.reader.read_until_to(needle, threshold, &mut tee) {
+            Ok(capture) => {
+                let before = match capture {
+                    Capture::Collected(ref output) => output.clone(),
+                    Capture::Streamed(_) => tee.copy.unwrap_or_default(),
+                };
+                self.record(&before, needle);
+                self.emit(|s| SessionEvent::Matched {
+                    before: match capture {
+                        Capture::Collected(ref output) => s.redact(output),
+                        // the output went to the writer
+                        Capture::Streamed(_) => String::new(),
+                    },
+                    matched: s.redact(needle),
+                });
+                Ok(capture)
+            }
+            Err(e) => {
+                // what was written is gone from the buffer, keep it in the transcript
+                if let Some(copy) = tee.copy {
+                    self.record(&copy, "");
+                }
+                Err(self.exp_error(e))
+            }
+        }
+    }
+
+    /// Merge lines appended to the file at `path` into the output of the process, tagged
+    /// with the file name, e.g. `[server.log] ready`. Useful for daemons which only
+    /// talk in their log files. See `NBReader::tail_file` for details.
+    pub fn tail_file<P: AsRef<std::path::Path>>(&mut self, path: P) -> Result<()> {
+        self.reader.tail_file(path)
+    }
+
+    /// Wait until we see EOF (i.e. child process has terminated)
+    /// Return all the yet unread output
+    pub fn exp_eof(&mut self) -> Result<String> {
+        self.exp(&ReadUntil::EOF).map(|(_, s)| s)
+    }
+
+    /// Wait until provided regex is seen on stdout of child process.
+    /// Return a tuple:
+    /// 1. the yet unread output
+    /// 2. the matched regex
+    ///
+    /// Note that `exp_regex("^foo")` matches the start of the yet consumed output.
+    /// For matching the start of the line use `exp_regex("\nfoo")`
+    pub fn exp_regex(&mut self, regex: &str) -> Result<(String, String)> {
+        self.exp(&ReadUntil::Regex(Regex::new(regex).chain_err(|| "invalid regex")?))
+    }
+
+    /// Wait for the regexes in `patterns` one after the other, each one within
+    /// `per_line_timeout` after the previous one matched, and return the matched texts.
+    /// Meant for protocol-like dialogs, where one overall timeout would hide which step
+    /// got stuck: the error tells which pattern didn't show up in time.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::session::spawn_command;
+    /// use std::process::Command;
+    /// use std::time::Duration;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut cmd = Command::new("bash");
+    /// cmd.args(["-c", "echo boot; sleep 0.2; echo login:; sleep 0.2; echo ok"]);
+    /// let mut p = spawn_command(cmd, Some(5000))?;
+    /// let lines = p.exp_lines(&["boot", "login:", "ok"], Duration::from_millis(500))?;
+    /// assert_eq!(vec!["boot", "login:", "ok"], lines);
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn exp_lines(&mut self, patterns: &[&str], per_line_timeout: time::Duration)
+                     -> Result<Vec<String>> {
+        let regexes = patterns
+            .iter()
+            .map(|p| Regex::new(p).chain_err(|| format!("invalid regex {}", p)))
+            .collect::<Result<Vec<_>>>()?;
+        self.with_timeout(Some(per_line_timeout), |s| {
+            regexes
+                .into_iter()
+                .enumerate()
+                .map(|(i, regex)| {
+                    s.exp(&ReadUntil::Regex(regex))
+                        .map(|(_, matched)| matched)
+                        .chain_err(|| format!("line {} of {} ({}) didn't show up within {} ms",
+                                              i + 1, patterns.len(), patterns[i],
+                                              per_line_timeout.as_millis()))
+                })
+                .collect()
+        })
+    }
+
+    /// Wait until provided string is seen on stdout of child process.
+    /// Return the yet unread output (without the matched string)
+    pub fn exp_string(&mut self, needle: &str) -> Result<String> {
+        self.exp(&ReadUntil::String(needle.to_string()))
+            .map(|(s, _)| s)
+    }
+
+    /// Wait until `text` is printed in the foreground `color` (set by ANSI escape
+    /// sequences). Return a tuple:
+    /// 1. the yet unread output
+    /// 2. the matched text, including escape sequences within it
+    ///
+    /// The same text in another color is ignored, so this asserts the presentation
+    /// of e.g. error messages, not only the content.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::session::spawn_command;
+    /// use rexpect::ansi::Color;
+    /// use std::process::Command;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut cmd = Command::new("printf");
+    /// cmd.arg(r"ERROR \033[31mERROR\033[0m");
+    /// let mut p = spawn_command(cmd, Some(1000))?;
+    /// let (before, _) = p.exp_colored("ERROR", Color::Red)?;
+    /// assert_eq!("ERROR \x1b[31m", before);
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn exp_colored(&mut self, text: &str, color: Color) -> Result<(String, String)> {
+        self.exp(&ReadUntil::Colored(text.to_string(), color))
+    }
+
+    /// Wait until the raw `needle` bytes are seen on stdout of child process.
+    /// Return the yet unread output as bytes (without the needle), unlike the `exp_*`
+    /// methods returning strings this is exactly what the process wrote.
+    pub fn exp_bytes(&mut self, needle: &[u8]) -> Result<Vec<u8>> {
+        self.exp(&ReadUntil::Bytes(needle.to_vec()))
+            .map(|(s, _)| reader::to_bytes(&s))
+    }
+
+    /// Read exactly `n` bytes of output, or less when EOF is reached before
+    pub fn read_bytes(&mut self, n: usize) -> Result<Vec<u8>> {
+        self.exp(&ReadUntil::NBytes(n))
+            .map(|(_, s)| reader::to_bytes(&s))
+    }
+
+    /// Wait until provided char is seen on stdout of child process.
+    /// Return the yet unread output (without the matched char)
+    pub fn exp_char(&mut self, needle: char) -> Result<String> {
+        self.exp(&ReadUntil::String(needle.to_string()))
+            .map(|(s, _)| s)
+    }
+
+    /// Wait until any of the provided needles is found.
+    ///
+    /// Return a tuple with:
+    /// 1. the yet unread string, without the matching needle (empty in case of EOF and NBytes)
+    /// 2. the matched string
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::{spawn, ReadUntil};
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut s = spawn("cat", Some(1000))?;
+    /// s.send_line("hello, polly!")?;
+    /// s.exp_any(vec![ReadUntil::String("hello".into()),
+    ///                ReadUntil::EOF])?;
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn exp_any(&mut self, needles: Vec<ReadUntil>) -> Result<(String, String)> {
+        self.exp(&ReadUntil::Any(needles))
+    }
+
+    /// Set the regex matching the prompt of the shell (or other command line program) for
+    /// `execute` and `wait_for_prompt`, e.g. `r"\$ $"`. Make it as specific as you can, it
+    /// must not match within the output of your commands.
+    pub fn set_prompt(&mut self, regex: &str) -> Result<()> {
+        self.prompt = Some(Regex::new(regex).chain_err(|| "invalid prompt regex")?);
+        Ok(())
+    }
+
+    /// Find out the prompt by sending empty lines and looking at what the process prints
+    /// each time, then use it for `execute` and `wait_for_prompt` (see `set_prompt`).
+    /// Returns the prompt.
+    ///
+    /// Fails if the prompt isn't the same every time (e.g. it contains the time) or nothing
+    /// shows up. Works best after the startup output is done, all unread output is
+    /// discarded.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn("sh", Some(2000))?;
+    /// p.send_line("PS1='$USER@sh> '")?;
+    /// p.learn_prompt()?;
+    /// assert_eq!("hello\r\n", p.execute("echo hello")?);
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn learn_prompt(&mut self) -> Result<String> {
+        // time after which we assume that the process is done printing the prompt
+        let idle = time::Duration::from_millis(300);
+        self.read_until_idle(idle)?;
+        let mut prompts = Vec::new();
+        for _ in 0..2 {
+            self.send_line("")?;
+            let output = self.read_until_idle(idle)?;
+            let last_line = output.rsplit('\n').next().unwrap_or("").trim_start_matches('\r');
+            prompts.push(last_line.to_string());
+        }
+        if prompts[0].is_empty() {
+            return Err("cannot learn prompt: no output after sending an empty line".into());
+        }
+        if prompts[0] != prompts[1] {
+            return Err(format!("cannot learn prompt: got {:?} and then {:?}, use set_prompt",
+                               prompts[0], prompts[1]).into());
+        }
+        self.set_prompt(&format!("{}$", regex::escape(&prompts[0])))?;
+        Ok(prompts.remove(0))
+    }
+
+    /// The prompt set with `set_prompt`
+    pub fn prompt(&self) -> Option<&Regex> {
+        self.prompt.as_ref()
+    }
+
+    /// Wait until the prompt (see `set_prompt`) shows up and return the output before it
+    pub fn wait_for_prompt(&mut self) -> Result<String> {
+        let prompt = match self.prompt {
+            Some(ref prompt) => prompt.clone(),
+            None => return Err("no prompt set, see `set_prompt`".into()),
+        };
+        self.exp(&ReadUntil::Regex(prompt)).map(|(before, _)| before)
+    }
+
+    /// Send `cmd`, wait until the prompt (see `set_prompt`) shows up again and return
+    /// the output of the command, without the echoed command and the prompt.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn("sh", Some(1000))?;
+    /// p.send_line("PS1='sh> '")?;
+    /// p.set_prompt("sh> $")?;
+    /// p.wait_for_prompt()?;
+    /// assert_eq!("hello\r\n", p.execute("echo hello")?);
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn execute(&mut self, cmd: &str) -> Result<String> {
+        self.send_line(cmd)?;
+        let output = self.wait_for_prompt()?;
+        Ok(strip_echo(&output, cmd).to_string())
+    }
+
+    /// Make all following `exp_*` calls fail fast with `ErrorKind::FailPattern` as soon
+    /// as `pattern` shows up in the output before the expected needle does.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::{spawn, ReadUntil};
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut s = spawn("cat", Some(1000))?;
+    /// s.add_fail_pattern(ReadUntil::String("Segmentation fault".into()));
+    /// s.send_line("Segmentation fault")?;
+    /// assert!(s.exp_string("login:").is_err());
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn add_fail_pattern(&mut self, pattern: ReadUntil) {
+        self.reader.add_fail_pattern(pattern);
+    }
+
+    /// Remove all patterns registered with `add_fail_pattern`
+    pub fn clear_fail_patterns(&mut self) {
+        self.reader.clear_fail_patterns();
+    }
+
+    /// Match the printed text only: escape sequences (colors, cursor movements, screen
+    /// clears, window titles, ...) are dropped from the output read from now on. Programs
+    /// which repaint the line or color their output are then matched with plain strings.
+    /// `ReadUntil::Colored` never matches with this.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::session::spawn_command;
+    /// use std::process::Command;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut cmd = Command::new("printf");
+    /// cmd.arg(r"\033[2J\033[1;1H\033[32mhello\033[0m world");
+    /// let mut s = spawn_command(cmd, Some(1000))?;
+    /// s.set_strip_escapes(true);
+    /// s.exp_string("hello world")?;
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn set_strip_escapes(&mut self, strip: bool) {
+        self.reader.set_strip_escapes(strip);
+    }
+}
+
+/// The master side of the pty of the process, see `PtyProcess`'s `AsRawFd`. Reading from
+/// it takes output away from the session.
+impl AsRawFd for PtySession {
+    fn as_raw_fd(&self) -> RawFd {
+        self.process.as_raw_fd()
+    }
+}
+
+impl fmt::Debug for PtySession {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let patterns = |handlers: &[(ReadUntil, PatternHandler)]| -> Vec<String> {
+            handlers.iter().map(|(pattern, _)| pattern.to_string()).collect()
+        };
+        let reader = self.redact(&format!("{:?}", self.reader));
+        f.debug_struct("PtySession")
+            .field("commandname", &format_args!("{}", self.commandname))
+            .field("process", &self.process)
+            .field("reader", &format_args!("{}", reader))
+            .field("line_terminator", &self.line_terminator)
+            .field("echo_check", &self.echo_check)
+            .field("flush_policy", &self.flush_policy)
+            .field("expect_before", &patterns(&self.expect_before))
+            .field("expect_after", &patterns(&self.expect_after))
+            .field("secrets", &self.secrets.len())
+            .finish()
+    }
+}
+
+/// Remove the echoed `cmd` from the start of its output, if it's there
+pub(crate) fn strip_echo<'a>(output: &'a str, cmd: &str) -> &'a str {
+    let output = output.trim_start_matches(['\r', '\n']);
+    match output.find('\n') {
+        Some(pos) if output[..pos].trim_end() == cmd => &output[pos + 1..],
+        _ => output,
+    }
+}
+
+/// Turn e.g. "prog arg1 arg2" into ["prog", "arg1", "arg2"]
+/// Also takes care of single and double quotes
+fn tokenize_command(program: &str) -> Vec<String> {
+    let re = Regex::new(r#""[^"]+"|'[^']+'|[^'" ]+"#).unwrap();
+    let mut res = vec![];
+    for cap in re.captures_iter(program) {
+        res.push(cap[0].to_string());
+    }
+    res
+}
+
+/// Start command in background in a pty session (pty fork) and return a struct
+/// with writer and buffered reader (for unblocking reads).
+///
+/// #Arguments:
+///
+/// - `program`: This is split at spaces and turned into a `process::Command`
+///   if you wish more control over this, use `spawn_command`
+/// - `timeout`: If Some: all `exp_*` commands time out after x millisecons, if None: never times
+///   out.
+///   It's higly recommended to put a timeout there, as otherwise in case of
+///   a problem the program just hangs instead of exiting with an
+///   error message indicating where it stopped.
+///   For automation 30'000 (30s, the default in pexpect) is a good value.
+pub fn spawn(program: &str, timeout_ms: Option<u64>) -> Result<PtySession> {
+    let command = if program.find(" ").is_some() {
+        let mut parts = tokenize_command(program);
+        let mut cmd = Command::new(&parts[0]);
+        parts.remove(0);
+        cmd.args(parts);
+        cmd
+    } else {
+        Command::new(program)
+    };
+    spawn_command(command, timeout_ms)
+}
+
+/// See `spawn`
+pub fn spawn_command(command: Command, timeout_ms: Option<u64>) -> Result<PtySession> {
+    spawn_with_options(command, timeout_ms, SpawnOptions::default())
+}
+
+/// Same as `spawn_command`, with more control over how the process is started,
+/// see `SpawnOptions`
+pub fn spawn_with_options(command: Command,
+                          timeout_ms: Option<u64>,
+                          options: SpawnOptions)
+                          -> Result<PtySession> {
+    let commandname = format!("{:?}", &command);
+    let read_inline = options.reads_inline();
+    let process = PtyProcess::new_with_options(command, options)
+        .chain_err(|| "couldn't start process")?;
+    new_session(process, commandname, timeout_ms, read_inline)
+}
+
+/// Start a session for a process which was not spawned by rexpect, see
+/// `PtyProcess::from_raw_parts`. The timeout is the same as for `spawn`.
+pub fn attach(process: PtyProcess, timeout_ms: Option<u64>) -> Result<PtySession> {
+    let commandname = format!("pid {}", process.child_pid);
+    new_session(process, commandname, timeout_ms, SpawnOptions::default().reads_inline())
+}
+
+fn new_session(mut process: PtyProcess,
+               commandname: String,
+               timeout_ms: Option<u64>,
+               read_inline: bool)
+               -> Result<PtySession> {
+    process.set_kill_timeout(timeout_ms);
+
+    let f = process.get_file_handle();
+    let writer = LineWriter::new(f.try_clone().chain_err(|| "couldn't open write stream")?);
+    let reader = if read_inline {
+        NBReader::new_inline(f, timeout_ms)
+    } else {
+        NBReader::new(f, timeout_ms)
+    };
+    Ok(PtySession {
+           process,
+           writer,
+           reader,
+           commandname,
+           expect_before: Vec::new(),
+           expect_after: Vec::new(),
+           line_terminator: LineTerminator::default(),
+           secrets: Vec::new(),
+           prompt: None,
+           echo_check: EchoCheck::default(),
+           flush_policy: FlushPolicy::default(),
+           last_flush: time::Instant::now(),
+           transcript: None,
+           transcript_retention: TranscriptRetention::default(),
+           event_sink: None,
+       })
+}
+
+/// The matcher state of a session at some point, see `PtySession::freeze`
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub reader: ReaderSnapshot,
+    /// number of chars of the transcript consumed at that point (including output
+    /// dropped by the retention), `None` if no transcript was recorded
+    pub position: Option<usize>,
+}
+
+impl Snapshot {
+    /// A reader in the frozen state, continuing with the output in `recording` after
+    /// what was already read at that point. `recording` is the transcript of the session
+    /// (see `PtySession::transcript`), positions are off if it contains masked secrets.
+    /// Without a transcript only the output buffered at that point is replayed.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::{spawn, ReadUntil};
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn("cat", Some(1000))?;
+    /// p.start_transcript();
+    /// p.send_line("hello")?;
+    /// let snapshot = p.freeze();
+    /// p.send_line("world")?;
+    /// p.exp_string("world")?;
+    /// let recording = p.transcript().unwrap();
+    ///
+    /// // later: try other patterns from the point of the snapshot
+    /// let mut replay = snapshot.replay(&recording);
+    /// replay.read_until(&ReadUntil::String("hello".into()))?;
+    /// replay.read_until(&ReadUntil::String("world".into()))?;
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn replay(&self, recording: &str) -> NBReader {
+        let rest: Vec<u8> = match self.position {
+            Some(position) => {
+                let skip = position + self.reader.buffer.chars().count();
+                reader::to_bytes(&recording.chars().skip(skip).collect::<String>())
+            }
+            None => Vec::new(),
+        };
+        self.reader.thaw(io::Cursor::new(rest))
+    }
+}
+
+/// Restores the previous timeout and deadline when dropped, see
+/// `PtySession::timeout_guard`
+pub struct TimeoutGuard<'a> {
+    session: &'a mut PtySession,
+    previous: Option<time::Duration>,
+    previous_deadline: Option<time::Instant>,
+}
+
+impl Deref for TimeoutGuard<'_> {
+    type Target = PtySession;
+    fn deref(&self) -> &PtySession {
+        self.session
+    }
+}
+
+impl DerefMut for TimeoutGuard<'_> {
+    fn deref_mut(&mut self) -> &mut PtySession {
+        self.session
+    }
+}
+
+impl Drop for TimeoutGuard<'_> {
+    fn drop(&mut self) {
+        self.session.reader.set_timeout(self.previous);
+        self.session.reader.set_deadline(self.previous_deadline);
+    }
+}
+
+/// Wait until `needle` shows up in the output of any of `sessions`, e.g. to see whether
+/// the client or the server of a pair under test reports an error first. Returns the
+/// index of the session which matched with the yet unread output and the match, just
+/// like `exp_any`. The `expect_before`/`expect_after` handlers are not run.
+///
+/// Waits as long as the longest timeout of the sessions (forever if one has none),
+/// fails as soon as one session reaches EOF.
+///
+/// # Example:
+///
+/// ```
+/// use rexpect::{spawn, ReadUntil};
+/// use rexpect::session::expect_on_any;
+/// # use rexpect::errors::*;
+///
+/// # fn main() {
+///     # || -> Result<()> {
+/// let mut server = spawn("cat", Some(1000))?;
+/// let mut client = spawn("cat", Some(1000))?;
+/// client.send_line("ready")?;
+/// let needle = ReadUntil::String("ready".to_string());
+/// let (index, _, _) = expect_on_any(&mut [&mut server, &mut client], &needle)?;
+/// assert_eq!(1, index);
+///         # Ok(())
+///     # }().expect("test failed");
+/// # }
+/// ```
+pub fn expect_on_any(sessions: &mut [&mut PtySession], needle: &ReadUntil)
+                     -> Result<(usize, String, String)> {
+    if sessions.is_empty() {
+        return Err("expect_on_any needs at least one session".into());
+    }
+    let timeout = sessions
+        .iter()
+        .map(|s| s.reader.timeout())
+        .collect::<Option<Vec<_>>>()
+        .and_then(|timeouts| timeouts.into_iter().max());
+    let start = time::Instant::now();
+    loop {
+        for (i, session) in sessions.iter_mut().enumerate() {
+            let found = session.try_exp(needle)
+                .chain_err(|| format!("session {} ({}) failed", i, session.commandname))?;
+            if let Some((first, second)) = found {
+                return Ok((i, first, second));
+            }
+        }
+        if let Some(timeout) = timeout {
+            if start.elapsed() > timeout {
+                let got = sessions
+                    .iter()
+                    .map(|s| s.redact(&reader::escaped_tail(s.reader.buffered(), 64)))
+                    .collect::<Vec<_>>()
+                    .join("\", \"");
+                return Err(ErrorKind::Timeout(needle.to_string(), got, timeout).into());
+            }
+        }
+        // the readers fill their channels in the background, just check them again
+        thread::sleep(time::Duration::from_millis(10));
+    }
+}
+
+/// A repl session: e.g. bash or the python shell:
+/// You have a prompt where a user inputs commands and the shell
+/// executes it and writes some output
+#[derive(Debug)]
+pub struct PtyReplSession {
+    /// the prompt, used for `wait_for_prompt`, e.g. ">>> " for python
+    pub prompt: String,
+
+    /// the pty_session you prepared before (initiating the shell, maybe set a custom prompt, etc.)
+    /// see `spawn_bash` for an example
+    pub pty_session: PtySession,
+
+    /// if set, then the quit_command is called when this object is dropped
+    /// you need to provide this if the shell you're testing is not killed by just sending
+    /// SIGTERM
+    pub quit_command: Option<String>,
+
+    /// set this to true if the repl has echo on (i.e. sends user input to stdout)
+    /// although echo is set off at pty fork (see `PtyProcess::new`) a few repls still
+    /// seem to be able to send output. You may need to try with true first, and if
+    /// tests fail set this to false.
+    pub echo_on: bool,
+}
+
+impl PtyReplSession {
+    pub fn wait_for_prompt(&mut self) -> Result<String> {
+        self.pty_session.exp_string(&self.prompt)
+    }
+
+    /// Send cmd to repl and:
+    /// 1. wait for the cmd to be echoed (if `echo_on == true`)
+    /// 2. wait for the ready string being present
+    ///
+    /// Q: Why can't I just do `send_line` and immediately continue?
+    /// A: Executing a command in e.g. bash causes a fork. If the Unix kernel chooses the
+    ///    parent process (bash) to go first and the bash process sends e.g. Ctrl-C then the
+    ///    Ctrl-C goes to nirvana.
+    ///    The only way to prevent this situation is to wait for a ready string being present
+    ///    in the output.
+    ///
+    /// Another safe way to tackle this problem is to use `send_line()` and `wait_for_prompt()`
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn_bash;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn_bash(Some(1000))?;
+    /// p.execute("cat <(echo ready) -", "ready")?;
+    /// p.send_line("hans")?;
+    /// p.exp_string("hans")?;
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn execute(&mut self, cmd: &str, ready_regex: &str) -> Result<()> {
+        self.send_line(cmd)?;
+        if self.echo_on {
+            self.exp_string(cmd)?;
+        }
+        self.exp_regex(ready_regex)?;
+        Ok(())
+    }
+
+    /// Send cmd to repl and wait for the prompt. The output is returned as with
+    /// `wait_for_prompt`, but once it grows beyond `threshold` bytes all of it is written
+    /// into `writer` while it's read instead, so it's fit for commands with huge outputs
+    /// like `journalctl`. See `PtySession::exp_string_to_writer`.
+    ///
+    /// The timeout only triggers if the command doesn't write anything for that long.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn_bash;
+    /// use rexpect::reader::Capture;
+    /// # use rexpect::errors::*;
+    ///
+    /// # fn main() {
+    ///     # || -> Result<()> {
+    /// let mut p = spawn_bash(Some(1000))?;
+    /// let mut output = Vec::new();
+    /// assert_eq!(Capture::Collected("1\r\n2\r\n3\r\n".to_string()),
+    ///            p.execute_to_writer("seq 1 3", 1024, &mut output)?);
+    /// assert_eq!(Capture::Streamed(4893), p.execute_to_writer("seq 1 1000", 1024, &mut output)?);
+    /// assert!(output.starts_with(b"1\r\n2\r\n"));
+    ///         # Ok(())
+    ///     # }().expect("test failed");
+    /// # }
+    /// ```
+    pub fn execute_to_writer<W: Write>(&mut self, cmd: &str, threshold: usize, writer: &mut W)
+                                       -> Result<Capture> {
+        self.send_line(cmd)?;
+        let prompt = self.prompt.clone();
+        self.exp_string_to_writer(&prompt, threshold, writer)
+    }
+
+    /// send line to repl (and flush output) and then, if echo_on=true wait for the
+    /// input to appear.
+    /// Return: number of bytes written
+    pub fn send_line(&mut self, line: &str) -> Result<usize> {
+        let bytes_written = self.pty_session.send_line(line)?;
+        if self.echo_on {
+            self.exp_string(line)?;
+        }
+        Ok(bytes_written)
+    }
+
+    /// Start a full screen program like `vim` or `less` from the shell and wait until it
+    /// took over the terminal (switched to the alternate screen). Interact with it through
+    /// the returned `ShellProgram`, then wait for it to give the terminal back to the
+    /// shell with `ShellProgram::wait_for_exit`.
+    ///
+    /// # Example:
+    ///
+    /// ```
+    /// use rexpect::spawn_bash;
+    /// # use rexpect::errors::*;
     ///
     /// # fn main() {
     ///     # || -> Result<()> {
     /// let mut p = spawn_bash(Some(1000))?;
-    /// p.execute("cat <(echo ready) -", "ready")?;
-    /// p.send_line("hans")?;
-    /// p.exp_string("hans")?;
+    /// // a minimal full screen program
+    /// let cmd = "printf '\\e[?1049hname? '; read name; printf '\\e[?1049l'";
+    /// let mut program = p.spawn_in_shell(cmd)?;
+    /// program.exp_string("name? ")?;
+    /// program.send_line("polly")?;
+    /// program.wait_for_exit()?;
     ///         # Ok(())
     ///     # }().expect("test failed");
     /// # }
     /// ```
-    pub fn execute(&mut self, cmd: &str, ready_regex: &str) -> Result<()> {
+    pub fn spawn_in_shell(&mut self, cmd: &str) -> Result<ShellProgram<'_>> {
         self.send_line(cmd)?;
-        if self.echo_on {
-            self.exp_string(cmd)?;
+        self.pty_session
+            .exp_regex(ALTERNATE_SCREEN_ON)
+            .chain_err(|| format!("{} didn't switch to the alternate screen", cmd))?;
+        Ok(ShellProgram { repl: self })
+    }
+}
+
+// the escape sequences switching to the alternate screen and back, the xterm ones
+// (1049 and 1047) and the old one (47)
+const ALTERNATE_SCREEN_ON: &str = r"\x1b\[\?(?:1049|1047|47)h";
+const ALTERNATE_SCREEN_OFF: &str = r"\x1b\[\?(?:1049|1047|47)l";
+
+/// A full screen program started with `PtyReplSession::spawn_in_shell`, derefs to the
+/// session to interact with the program
+pub struct ShellProgram<'a> {
+    repl: &'a mut PtyReplSession,
+}
+
+impl<'a> ShellProgram<'a> {
+    /// Wait until the program switched back from the alternate screen and the shell
+    /// prompt shows up again. Returns the output printed in between, e.g. a message the
+    /// program prints on exit.
+    pub fn wait_for_exit(self) -> Result<String> {
+        self.repl
+            .pty_session
+            .exp_regex(ALTERNATE_SCREEN_OFF)
+            .chain_err(|| "the program didn't leave the alternate screen")?;
+        self.repl.wait_for_prompt()
+    }
+}
+
+impl<'a> Deref for ShellProgram<'a> {
+    type Target = PtySession;
+    fn deref(&self) -> &PtySession {
+        &self.repl.pty_session
+    }
+}
+
+impl<'a> DerefMut for ShellProgram<'a> {
+    fn deref_mut(&mut self) -> &mut PtySession {
+        &mut self.repl.pty_session
+    }
+}
+
+// make PtySession's methods available directly
+impl Deref for PtyReplSession {
+    type Target = PtySession;
+    fn deref(&self) -> &PtySession {
+        &self.pty_session
+    }
+}
+
+impl DerefMut for PtyReplSession {
+    fn deref_mut(&mut self) -> &mut PtySession {
+        &mut self.pty_session
+    }
+}
+
+impl Drop for PtyReplSession {
+    /// for e.g. bash we *need* to run `quit` at the end.
+    /// if we leave that out, PtyProcess would try to kill the bash
+    /// which would not work, as a SIGTERM is not enough to kill bash
+    /// (it gets SIGKILL after the kill timeout then).
+    ///
+    /// The command is only sent if the pty takes input, a program which doesn't read
+    /// (e.g. `sleep 9999`) must not block the drop.
+    fn drop(&mut self) {
+        if let Some(ref cmd) = self.quit_command {
+            let fd = self.pty_session.writer.get_ref().as_raw_fd();
+            let mut fds = [PollFd::new(fd, PollFlags::POLLOUT)];
+            if let Ok(1) = poll(&mut fds, 100) {
+                // the process may already be gone
+                let _ = self.pty_session.send_line(cmd);
+            }
         }
-        self.exp_regex(ready_regex)?;
-        Ok(())
+    }
+}
+
+
+/// Spawn bash in a pty session, run programs and expect output
+///
+///
+/// The difference to `spawn` and `spawn_command` is:
+///
+/// - spawn_bash starts bash with a custom rcfile which guarantees
+///   a certain prompt
+/// - the PtyBashSession also provides `wait_for_prompt` and `execute`
+///
+/// timeout: the duration until which `exp_*` returns a timeout error, or None
+/// additionally, when dropping the bash prompt while bash is still blocked by a program
+/// (e.g. `sleep 9999`) then the timeout is used as a timeout before a `kill -9` is issued
+/// at the bash command. Use a timeout whenever possible because it makes
+/// debugging a lot easier (otherwise the program just hangs and you
+/// don't know where)
+///
+/// bash is started with echo off. That means you don't need to "read back"
+/// what you wrote to bash. But what you need to do is a `wait_for_prompt`
+/// after a process finished.
+///
+/// Also: if you start a program you should use `execute` and not `send_line`.
+///
+/// The prompt contains a random nonce (see `PtyReplSession::prompt`), so a program
+/// which happens to print the prompt of another session can't be mistaken for bash
+/// being done.
+///
+/// For an example see the README
+pub fn spawn_bash(timeout: Option<u64>) -> Result<PtyReplSession> {
+    // unfortunately working with a temporary tmpfile is the only
+    // way to guarantee that we are "in step" with the prompt
+    // all other attempts were futile, especially since we cannot
+    // wait for the first prompt since we don't know what .bashrc
+    // would set as PS1 and we cannot know when is the right time
+    // to set the new PS1
+    let mut rcfile = tempfile::NamedTempFile::new().unwrap();
+    rcfile.write_all(b"include () { [[ -f \"$1\" ]] && source \"$1\"; }\n\
+                  include /etc/bash.bashrc\n\
+                  include ~/.bashrc\n\
+                  bind 'set enable-bracketed-paste off'\n\
+                  PS1=\"~~~~\"\n\
+                  unset PROMPT_COMMAND\n").expect("cannot write to tmpfile");
+    let mut c = Command::new("bash");
+    c.args(["--rcfile", rcfile.path().to_str().unwrap_or("temp file does not exist")]);
+    let new_prompt = format!("[REXPECT_PROMPT_{}>", nonce()?);
+    spawn_command(c, timeout).and_then(|mut p| {
+        p.set_prompt(&regex::escape(&new_prompt))?;
+        let mut pb = PtyReplSession {
+            prompt: new_prompt.clone(),
+            pty_session: p,
+            quit_command: Some("quit".to_string()),
+            echo_on: false,
+        };
+        pb.exp_string("~~~~")?;
+        rcfile.close().chain_err(|| "cannot delete temporary rcfile")?;
+        pb.send_line(&("PS1='".to_string() + &new_prompt + "'"))?;
+        // wait until the new prompt appears
+        pb.wait_for_prompt()?;
+        Ok(pb)
+    })
+}
+
+// random hex string for prompts which can't show up by accident
+fn nonce() -> Result<String> {
+    let mut bytes = [0u8; 8];
+    File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .chain_err(|| "cannot read /dev/urandom")?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Spawn the python shell
+///
+/// This is just a proof of concept implementation (and serves for documentation purposes)
+pub fn spawn_python(timeout: Option<u64>) -> Result<PtyReplSession> {
+    spawn_command(Command::new("python"), timeout).and_then(|mut p| {
+        p.set_prompt(&regex::escape(">>> "))?;
+        Ok(PtyReplSession {
+            prompt: ">>> ".to_string(),
+            pty_session: p,
+            quit_command: Some("exit()".to_string()),
+            echo_on: true,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_line() {
+        || -> Result<()> {
+            let mut s = spawn("cat", Some(1000))?;
+            s.send_line("hans")?;
+            assert_eq!("hans", s.read_line()?);
+            let should = crate::process::wait::WaitStatus::Signaled(s.process.child_pid,
+                                                               crate::process::signal::Signal::SIGTERM,
+                                                               false);
+            assert_eq!(should, s.process.exit()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_read_line failed: {}", e));
+    }
+
+
+    #[test]
+    fn test_line_terminator() {
+        || -> Result<()> {
+            let mut s = spawn("cat", Some(1000))?;
+            // the tty turns the carriage return into a newline for cat
+            s.set_line_terminator(LineTerminator::Cr);
+            assert_eq!(5, s.send_line("hans")?);
+            assert_eq!("hans", s.read_line()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_line_terminator failed: {}", e));
+    }
+
+    #[test]
+    fn test_send_eof() {
+        || -> Result<()> {
+            let mut p = spawn("wc -c", Some(1000))?;
+            p.send_line("hello")?;
+            p.send_eof()?;
+            // the tty turns the newline into \r\n only on output, wc sees 6 bytes
+            p.exp_string("6")?;
+            p.exp_eof()?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_send_eof failed: {}", e));
+    }
+
+    #[test]
+    fn test_drop_never_blocks() {
+        fn dropped_within<T>(stage: &str, value: T) {
+            let start = time::Instant::now();
+            drop(value);
+            let limit = crate::process::DROP_KILL_TIMEOUT + time::Duration::from_secs(2);
+            assert!(start.elapsed() < limit, "{}: drop took {:?}", stage, start.elapsed());
+        }
+        || -> Result<()> {
+            dropped_within("spawned", spawn("cat", None)?);
+
+            let mut p = spawn("true", None)?;
+            p.exp_eof()?;
+            dropped_within("exited", p);
+
+            let options = SpawnOptions::new().start_suspended(true);
+            dropped_within("suspended", spawn_with_options(Command::new("cat"), None, options)?);
+
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "trap '' TERM; echo ready; while :; do sleep 0.05; done"]);
+            let mut p = spawn_command(cmd, None)?;
+            p.exp_string("ready")?;
+            dropped_within("ignoring SIGTERM", p);
+
+            // never read, blocks on writing
+            let mut p = spawn("yes", None)?;
+            p.exp_string("y")?;
+            dropped_within("output not read", p);
+
+            // bash doesn't read the quit command, sleep doesn't react to SIGTERM sent to bash
+            let mut p = spawn_bash(None)?;
+            p.send_line("sleep 9999")?;
+            p.send("unread input without a line end")?;
+            dropped_within("busy repl", p);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_drop_never_blocks failed: {}", e));
+    }
+
+    #[test]
+    fn test_read_inline() {
+        || -> Result<()> {
+            let options = SpawnOptions::new().read_inline(true);
+            let mut p = spawn_with_options(Command::new("cat"), Some(300), options)?;
+            p.send_line("hello")?;
+            p.exp_string("hello")?;
+            let start = time::Instant::now();
+            match p.exp_string("never") {
+                Err(Error(ErrorKind::Timeout(..), _)) => {}
+                r => panic!("expected timeout, got {:?}", r),
+            }
+            let elapsed = start.elapsed();
+            assert!(elapsed >= time::Duration::from_millis(300), "{:?}", elapsed);
+            assert!(elapsed < time::Duration::from_secs(2), "{:?}", elapsed);
+            p.send_eof()?;
+            p.exp_eof()?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_read_inline failed: {}", e));
+    }
+
+    #[test]
+    fn test_keep_slave_open() {
+        || -> Result<()> {
+            // the worker opens the terminal again after bash exited, it inherits ignoring
+            // the SIGHUP it gets when bash exits
+            let script = "trap '' HUP; tty=$(tty); (exec </dev/null >/dev/null 2>&1; \
+                          sleep 0.3; echo worker >$tty) & echo started";
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", script]);
+            let mut p = spawn_command(cmd, Some(2000))?;
+            p.exp_string("started")?;
+            match p.exp_string("worker") {
+                Err(Error(ErrorKind::EOF(..), _)) => {}
+                r => panic!("expected EOF, got {:?}", r),
+            }
+
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", script]);
+            let options = SpawnOptions::new().keep_slave_open(true);
+            let mut p = spawn_with_options(cmd, Some(2000), options)?;
+            p.exp_string("started")?;
+            p.exp_string("worker")?;
+            p.process.close_slave();
+            p.exp_eof()?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_keep_slave_open failed: {}", e));
+    }
+
+    #[test]
+    fn test_start_suspended() {
+        || -> Result<()> {
+            let mut cmd = Command::new("echo");
+            cmd.arg("started");
+            let options = SpawnOptions::new().start_suspended(true);
+            let mut p = spawn_with_options(cmd, Some(200), options)?;
+            assert!(p.exp_string("started").is_err());
+            p.process.resume()?;
+            p.exp_string("started")?;
+            p.exp_eof()?;
+
+            // killing a suspended process works as well
+            let options = SpawnOptions::new().start_suspended(true);
+            let mut p = spawn_with_options(Command::new("cat"), Some(1000), options)?;
+            assert_eq!(nix::sys::wait::WaitStatus::Signaled(p.process.child_pid,
+                                                            nix::sys::signal::SIGTERM,
+                                                            false),
+                       p.process.exit()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_start_suspended failed: {}", e));
+    }
+
+    #[test]
+    fn test_interrupt() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            // make sure cat runs, before the terminal has no process to signal
+            p.send_line("hi")?;
+            p.exp_string("hi")?;
+            p.interrupt(Interrupt::Key)?;
+            p.exp_eof()?;
+
+            // in raw mode Ctrl-C is just a byte
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "stty raw; echo ready; cat"]);
+            let mut p = spawn_command(cmd, Some(1000))?;
+            p.exp_string("ready")?;
+            p.interrupt(Interrupt::Key)?;
+            p.exp_string("\x03")?;
+            p.interrupt(Interrupt::Signal)?;
+            p.exp_eof()?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_interrupt failed: {}", e));
+    }
+
+    #[test]
+    fn test_send_bytes() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "stty raw -echo; printf ready; cat"]);
+            let mut p = spawn_command(cmd, Some(1000))?;
+            p.exp_string("ready")?;
+            let data = [0x00, 0xff, 0x80, b'\n', 0x7f, 0xc3, 0x28];
+            assert_eq!(7, p.send_bytes(&data)?);
+            assert_eq!(vec![0x00, 0xff, 0x80, b'\n'], p.exp_bytes(&[0x7f])?);
+            assert_eq!(vec![0xc3, 0x28], p.read_bytes(2)?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_send_bytes failed: {}", e));
+    }
+
+    #[test]
+    fn test_send_key() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "stty raw -echo; printf ready; cat"]);
+            let mut p = spawn_command(cmd, Some(1000))?;
+            p.exp_string("ready")?;
+            p.send_key(Key::Up)?;
+            p.send_key('x')?;
+            p.send_keys(&[Key::F5, Key::Ctrl(ControlCode::Escape), Key::Enter])?;
+            assert_eq!(b"\x1b[Ax\x1b[15~\x1b\r".to_vec(), p.read_bytes(11)?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_send_key failed: {}", e));
+    }
+
+    #[test]
+    fn test_send_secret() {
+        let mut p = spawn("cat", Some(500)).expect("cannot run cat");
+        p.send_line("Password: ").unwrap();
+        p.answer_password("Password: ", "hunter2").unwrap();
+        // cat prints the secret back, which must not show up in the error
+        match p.exp_string("never printed") {
+            Err(Error(ErrorKind::Timeout(_, got, _), _)) => {
+                assert!(!got.contains("hunter2"), "got {:?}", got);
+                assert!(got.contains("********"), "got {:?}", got);
+            }
+            r => panic!("expected timeout, got {:?}", r.map_err(|e| e.to_string())),
+        }
+    }
+
+    #[test]
+    fn test_debug() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            p.send_secret("hunter2")?;
+            p.exp_string("hunter2")?;
+            p.send_line("x\ty")?;
+            p.exp_string("x")?;
+            p.reader.wait_until(&ReadUntil::String("\n".to_string()))?;
+            let debug = format!("{:?}", p);
+            assert!(debug.contains(&format!("child_pid: {:?}", p.process.child_pid)), "{}", debug);
+            assert!(debug.contains("alive: true"), "{}", debug);
+            assert!(debug.contains(r#""\ty\r\n""#), "{}", debug);
+            assert!(debug.contains("secrets: 1"), "{}", debug);
+            p.send_secret("hunter2")?;
+            let debug = format!("{:?}", p);
+            assert!(!debug.contains("hunter2"), "{}", debug);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_debug failed: {}", e));
+    }
+
+    #[test]
+    fn test_execute_output() {
+        || -> Result<()> {
+            let mut p = spawn_bash(Some(1000))?;
+            assert_eq!("a\r\nb\r\n", p.pty_session.execute("printf 'a\\nb\\n'")?);
+            assert_eq!("", p.pty_session.execute("true")?);
+
+            // with echo on the echoed command is stripped
+            let mut p = spawn("sh", Some(1000))?;
+            p.send_line("stty echo; PS1='sh> '")?;
+            p.set_prompt("sh> $")?;
+            p.wait_for_prompt()?;
+            assert_eq!("hi\r\n", p.execute("echo hi")?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_execute_output failed: {}", e));
+    }
+
+    #[test]
+    fn test_echo_check() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "stty echo; echo ready; cat"]);
+            let mut p = spawn_command(cmd, Some(1000))?;
+            p.exp_string("ready\r\n")?;
+            // the output is the echo of the line followed by cat printing it
+            p.set_echo_check(EchoCheck::Strip);
+            p.send_line("hello")?;
+            assert_eq!("hello", p.read_line()?);
+
+            p.set_echo_check(EchoCheck::Wait);
+            p.send_line("world")?;
+            assert!(p.reader.buffered().starts_with("world"));
+            assert_eq!("world", p.read_line()?);
+            assert_eq!("world", p.read_line()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_echo_check failed: {}", e));
+    }
+
+    #[test]
+    fn test_learn_prompt() {
+        || -> Result<()> {
+            let mut p = spawn("sh", Some(2000))?;
+            p.send_line("PS1='[sh]$ '")?;
+            assert_eq!("[sh]$ ", p.learn_prompt()?);
+            assert_eq!(r"\[sh\]\$ $", p.prompt().unwrap().as_str());
+            assert_eq!("1\r\n", p.execute("echo 1")?);
+
+            p.send_line("PS1='$(date +%N)> '")?;
+            assert!(p.learn_prompt().is_err());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_learn_prompt failed: {}", e));
+    }
+
+    #[test]
+    fn test_window_size() {
+        || -> Result<()> {
+            // the trap only sets a flag, so no signal gets lost while printing
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "trap 'w=1' WINCH; echo ready; while true; do \
+                             [ -n \"$w\" ] && w= && echo winch $(stty size); sleep 0.05; done"]);
+            let mut p = spawn_command(cmd, Some(2000))?;
+            p.exp_string("ready")?;
+            p.process.set_window_size(100, 40)?;
+            assert_eq!((100, 40), p.process.window_size()?);
+            p.exp_string("winch 40 100")?;
+            // same size: only notify_winch triggers the signal
+            p.process.set_window_size(100, 40)?;
+            p.process.notify_winch()?;
+            p.exp_string("winch 40 100")?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_window_size failed: {}", e));
     }
 
-    /// send line to repl (and flush output) and then, if echo_on=true wait for the
-    /// input to appear.
-    /// Return: number of bytes written
-    pub fn send_line(&mut self, line: &str) -> Result<usize> {
-        let bytes_written = self.pty_session.send_line(line)?;
-        if self.echo_on {
-            self.exp_string(line)?;
-        }
-        Ok(bytes_written)
+    #[test]
+    fn test_resize() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "trap 'w=1' WINCH; echo ready; while true; do \
+                             [ -n \"$w\" ] && w= && echo winch $(stty size); sleep 0.05; done"]);
+            let mut p = spawn_command(cmd, Some(2000))?;
+            p.exp_string("ready")?;
+            p.process.resize(90, 30)?;
+            p.exp_string("winch 30 90")?;
+            // the same size again still gets a SIGWINCH, but only one
+            p.process.resize(90, 30)?;
+            p.exp_string("winch 30 90")?;
+            let rest = p.read_until_idle(std::time::Duration::from_millis(300))?;
+            assert!(!rest.contains("winch"), "got {:?}", rest);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_resize failed: {}", e));
     }
-}
 
-// make PtySession's methods available directly
-impl Deref for PtyReplSession {
-    type Target = PtySession;
-    fn deref(&self) -> &PtySession {
-        &self.pty_session
+    #[test]
+    fn test_flush_policy() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            // input is flushed before waiting for output, even without a newline
+            p.send("abc")?;
+            p.send_control('d')?;
+            p.exp_string("abc")?;
+
+            let mut p = spawn("cat", Some(1000))?;
+            p.set_flush_policy(FlushPolicy::Always);
+            p.send("hello")?;
+            p.send_control('d')?;
+            // read directly to bypass the flush of `exp_*`
+            p.reader.wait_until(&ReadUntil::String("hello".to_string()))?;
+
+            let mut p = spawn("cat", Some(1000))?;
+            p.set_flush_policy(FlushPolicy::Interval(time::Duration::from_secs(60)));
+            p.flush()?;
+            p.send("held back")?;
+            assert!(p.reader.wait_until(&ReadUntil::String("held".to_string())).is_err());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_flush_policy failed: {}", e));
     }
-}
 
-impl DerefMut for PtyReplSession {
-    fn deref_mut(&mut self) -> &mut PtySession {
-        &mut self.pty_session
+    #[test]
+    fn test_transcript() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            assert_eq!(None, p.transcript());
+            p.start_transcript();
+            p.send_line("hello")?;
+            p.read_line()?;
+            p.send_secret("hunter2")?;
+            p.exp_string("hunter2")?;
+            let transcript = p.transcript().unwrap();
+            assert!(transcript.starts_with("hello\r\n********"), "{:?}", transcript);
+            assert!(!transcript.contains("hunter2"));
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_transcript failed: {}", e));
     }
-}
 
-impl Drop for PtyReplSession {
-    /// for e.g. bash we *need* to run `quit` at the end.
-    /// if we leave that out, PtyProcess would try to kill the bash
-    /// which would not work, as a SIGTERM is not enough to kill bash
-    fn drop(&mut self) {
-        if let Some(ref cmd) = self.quit_command {
-            self.pty_session
-                .send_line(&cmd)
-                .expect("could not run `exit` on bash process");
-        }
+    #[test]
+    fn test_expect_on_any() {
+        || -> Result<()> {
+            let mut server = spawn("cat", Some(500))?;
+            let mut client = spawn("cat", Some(1000))?;
+            let needle = ReadUntil::Regex(Regex::new("up|down").unwrap());
+            server.send_line("server up")?;
+            let (i, before, matched) = expect_on_any(&mut [&mut server, &mut client], &needle)?;
+            assert_eq!(("server ", "up"), (before.as_str(), matched.as_str()));
+            assert_eq!(0, i);
+            client.send_line("client down")?;
+            let mut sessions = [&mut server, &mut client];
+            assert_eq!(1, expect_on_any(&mut sessions, &needle)?.0);
+
+            let start = time::Instant::now();
+            match expect_on_any(&mut sessions, &needle) {
+                Err(Error(ErrorKind::Timeout(..), _)) => {}
+                r => panic!("expected timeout, got {:?}", r),
+            }
+            // the longest timeout counts
+            assert!(start.elapsed() >= time::Duration::from_millis(1000));
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_expect_on_any failed: {}", e));
     }
-}
 
+    #[test]
+    fn test_with_timeout() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(100))?;
+            let long = Some(time::Duration::from_secs(5));
+            let start = time::Instant::now();
+            let res = p.with_timeout(Some(time::Duration::from_millis(300)),
+                                     |p| p.exp_string("never"));
+            assert!(res.is_err());
+            assert!(start.elapsed() >= time::Duration::from_millis(300));
+            assert_eq!(Some(time::Duration::from_millis(100)), p.reader.timeout());
 
-/// Spawn bash in a pty session, run programs and expect output
-///
-///
-/// The difference to `spawn` and `spawn_command` is:
-///
-/// - spawn_bash starts bash with a custom rcfile which guarantees
-///   a certain prompt
-/// - the PtyBashSession also provides `wait_for_prompt` and `execute`
-///
-/// timeout: the duration until which `exp_*` returns a timeout error, or None
-/// additionally, when dropping the bash prompt while bash is still blocked by a program
-/// (e.g. `sleep 9999`) then the timeout is used as a timeout before a `kill -9` is issued
-/// at the bash command. Use a timeout whenever possible because it makes
-/// debugging a lot easier (otherwise the program just hangs and you
-/// don't know where)
-///
-/// bash is started with echo off. That means you don't need to "read back"
-/// what you wrote to bash. But what you need to do is a `wait_for_prompt`
-/// after a process finished.
-///
-/// Also: if you start a program you should use `execute` and not `send_line`.
-///
-/// For an example see the README
-pub fn spawn_bash(timeout: Option<u64>) -> Result<PtyReplSession> {
-    // unfortunately working with a temporary tmpfile is the only
-    // way to guarantee that we are "in step" with the prompt
-    // all other attempts were futile, especially since we cannot
-    // wait for the first prompt since we don't know what .bashrc
-    // would set as PS1 and we cannot know when is the right time
-    // to set the new PS1
-    let mut rcfile = tempfile::NamedTempFile::new().unwrap();
-    rcfile.write(b"include () { [[ -f \"$1\" ]] && source \"$1\"; }\n\
-                  include /etc/bash.bashrc\n\
-                  include ~/.bashrc\n\
-                  PS1=\"~~~~\"\n\
-                  unset PROMPT_COMMAND\n").expect("cannot write to tmpfile");
-    let mut c = Command::new("bash");
-    c.args(&["--rcfile", rcfile.path().to_str().unwrap_or_else(|| return "temp file does not exist".into())]);
-    spawn_command(c, timeout).and_then(|p| {
-        let new_prompt = "[REXPECT_PROMPT>";
-        let mut pb = PtyReplSession {
-            prompt: new_prompt.to_string(),
-            pty_session: p,
-            quit_command: Some("quit".to_string()),
-            echo_on: false,
-        };
-        pb.exp_string("~~~~")?;
-        rcfile.close().chain_err(|| "cannot delete temporary rcfile")?;
-        pb.send_line(&("PS1='".to_string() + new_prompt + "'"))?;
-        // wait until the new prompt appears
-        pb.wait_for_prompt()?;
-        Ok(pb)
-    })
-}
+            let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                p.with_timeout(long, |_| panic!("in with_timeout"))
+            }));
+            assert!(panicked.is_err());
+            assert_eq!(Some(time::Duration::from_millis(100)), p.reader.timeout());
 
-/// Spawn the python shell
-///
-/// This is just a proof of concept implementation (and serves for documentation purposes)
-pub fn spawn_python(timeout: Option<u64>) -> Result<PtyReplSession> {
-    spawn_command(Command::new("python"), timeout).and_then(|p| {
-        Ok(PtyReplSession {
-            prompt: ">>> ".to_string(),
-            pty_session: p,
-            quit_command: Some("exit()".to_string()),
-            echo_on: true,
-        })
-    })
-}
+            {
+                let mut guard = p.timeout_guard(None);
+                guard.send_line("hello")?;
+                guard.exp_string("hello")?;
+                assert_eq!(None, guard.reader.timeout());
+            }
+            assert_eq!(Some(time::Duration::from_millis(100)), p.reader.timeout());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_with_timeout failed: {}", e));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_exp_lines() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "echo one; sleep 0.1; echo two; sleep 1; echo three"]);
+            let mut p = spawn_command(cmd, Some(5000))?;
+            let err = p.exp_lines(&["one", "t.o", "three"], time::Duration::from_millis(500))
+                .expect_err("three is too late");
+            assert!(err.to_string().starts_with("line 3 of 3 (three) didn't show up"), "{}", err);
+            let cause = err.iter().nth(1).map(|e| e.to_string()).unwrap_or_default();
+            assert!(cause.starts_with("Timeout Error: Expected Regex: \"three\""), "{}", cause);
+            // the timeout of the session is restored
+            assert_eq!(Some(time::Duration::from_millis(5000)), p.reader.timeout());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_exp_lines failed: {}", e));
+    }
 
     #[test]
-    fn test_read_line() {
+    fn test_with_deadline() {
         || -> Result<()> {
-            let mut s = spawn("cat", Some(1000))?;
-            s.send_line("hans")?;
-            assert_eq!("hans", s.read_line()?);
-            let should = crate::process::wait::WaitStatus::Signaled(s.process.child_pid,
-                                                               crate::process::signal::Signal::SIGTERM,
-                                                               false);
-            assert_eq!(should, s.process.exit()?);
+            let mut p = spawn("cat", Some(400))?;
+            let start = time::Instant::now();
+            let deadline = start + time::Duration::from_millis(600);
+            let res = p.with_deadline(deadline, |p| -> Result<()> {
+                p.send_line("one")?;
+                p.exp_string("one")?;
+                // each call on its own would be within the timeout
+                assert!(p.exp_string("never").is_err());
+                p.exp_string("never")?;
+                Ok(())
+            });
+            match res {
+                Err(Error(ErrorKind::Timeout(..), _)) => {}
+                r => panic!("expected timeout, got {:?}", r),
+            }
+            let elapsed = start.elapsed();
+            assert!(elapsed >= time::Duration::from_millis(600));
+            assert!(elapsed < time::Duration::from_millis(800 + 100), "{:?}", elapsed);
+            assert_eq!(None, p.reader.deadline());
+
+            let past = time::Instant::now();
+            p.send_line("two")?;
+            thread::sleep(time::Duration::from_millis(100));
+            // output which is already there still matches
+            p.exp_until(&ReadUntil::String("two".to_string()), past)?;
+            assert!(p.exp_until(&ReadUntil::NBytes(100), past).is_err());
             Ok(())
         }()
-                .unwrap_or_else(|e| panic!("test_read_line failed: {}", e));
+                .unwrap_or_else(|e| panic!("test_with_deadline failed: {}", e));
     }
 
+    #[test]
+    fn test_transcript_retention() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            p.start_transcript();
+            for line in &["one", "two", "three"] {
+                p.send_line(line)?;
+                p.read_line()?;
+            }
+            p.set_transcript_retention(TranscriptRetention::LastMatches(2));
+            assert_eq!(Some("[5 bytes of earlier output dropped]\ntwo\r\nthree\r\n".to_string()),
+                       p.transcript());
+            p.send_line("four")?;
+            p.read_line()?;
+            assert_eq!(Some("[10 bytes of earlier output dropped]\nthree\r\nfour\r\n".to_string()),
+                       p.transcript());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_transcript_retention failed: {}", e));
+    }
 
     #[test]
-    fn test_expect_eof_timeout() {
+    fn test_forward_events() {
+        || -> Result<()> {
+            let (tx, rx) = std::sync::mpsc::channel();
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "read line; echo \"got $line\"; echo oops; exit 2"]);
+            let mut p = spawn_command(cmd, Some(1000))?;
+            p.forward_events(move |event| { let _ = tx.send(event); });
+            p.send_line("one")?;
+            p.exp_string("one")?;
+            p.add_fail_pattern(ReadUntil::String("oops".to_string()));
+            assert!(p.exp_string("never").is_err());
+            p.clear_fail_patterns();
+            assert!(p.exp_string("never").is_err());
+            let pid = p.process.child_pid;
+            drop(p);
+            let events: Vec<SessionEvent> = rx.iter().collect();
+            assert_eq!(SessionEvent::Matched { before: "got ".into(), matched: "one".into() },
+                       events[0]);
+            assert_eq!(SessionEvent::FailPattern {
+                           pattern: "\"oops\"".into(),
+                           got: "\r\noops".into(),
+                       },
+                       events[1]);
+            // the pty may close before the exit status is there
+            match events[2] {
+                SessionEvent::Exited(None) => {}
+                SessionEvent::Exited(Some(wait::WaitStatus::Exited(p, 2))) if p == pid => {}
+                ref e => panic!("expected exit, got {:?}", e),
+            }
+            match events[3] {
+                SessionEvent::Failed(ref e) => assert!(e.starts_with("EOF"), "{}", e),
+                ref e => panic!("expected failure, got {:?}", e),
+            }
+            assert_eq!(4, events.len());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_forward_events failed: {}", e));
+    }
+
+    #[test]
+    fn test_exp_with() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            p.start_transcript();
+            p.send_line("Warning: low battery")?;
+            let warning = ReadUntil::Regex(Regex::new("low \\w+").unwrap());
+            assert_eq!(("Warning: ".to_string(), "low battery".to_string()),
+                       p.exp_with(&warning, Consume::Nothing)?);
+            p.exp_with(&ReadUntil::String("Warning".to_string()), Consume::UntilMatch)?;
+            assert_eq!("Warning: low battery", p.read_line()?);
+            assert_eq!(Some("Warning: low battery\r\n".to_string()), p.transcript());
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_exp_with failed: {}", e));
+    }
+
+    #[test]
+    fn test_freeze() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            p.start_transcript();
+            p.send_line("one")?;
+            p.exp_string("on")?;
+            p.send_line("two")?;
+            p.reader.wait_until(&ReadUntil::String("two".to_string()))?;
+            let snapshot = p.freeze();
+            p.send_line("three")?;
+            p.exp_string("three")?;
+            let recording = p.transcript().unwrap();
+
+            let mut replay = snapshot.replay(&recording);
+            assert_eq!(("e\r\n".to_string(), "two".to_string()),
+                       replay.read_until(&ReadUntil::String("two".to_string()))?);
+            assert_eq!(("\r\nthr".to_string(), "ee".to_string()),
+                       replay.read_until(&ReadUntil::String("ee".to_string()))?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_freeze failed: {}", e));
+    }
+
+    #[test]
+    fn test_output_after_exit() {
+        || -> Result<()> {
+            let mut cmd = Command::new("bash");
+            cmd.args(["-c", "printf 'one\\ntwo\\nthree\\n'; exit 3"]);
+            let mut p = spawn_command(cmd, Some(1000))?;
+            assert_eq!(nix::sys::wait::WaitStatus::Exited(p.process.child_pid, 3), p.process.wait()?);
+            assert_eq!("one", p.read_line()?);
+            match p.exp_string("four") {
+                Err(Error(ErrorKind::EOF(_, got, _), _)) => assert_eq!("two\r\nthree\r\n", got),
+                r => panic!("expected EOF error, got {:?}", r),
+            }
+            // the failed expect didn't consume anything
+            assert_eq!("two", p.read_line()?);
+            assert_eq!("three\r\n", p.exp_eof()?);
+            assert_eq!("", p.exp_eof()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_output_after_exit failed: {}", e));
+    }
+
+    #[test]
+    fn test_nested_sessions() {
         || -> Result<()> {
-            let mut p = spawn("sleep 3", Some(1000)).expect("cannot run sleep 3");
-            match p.exp_eof() {
-                Ok(_) => assert!(false, "should raise Timeout"),
-                Err(Error(ErrorKind::Timeout(_, _, _), _)) => {}
-                Err(_) => assert!(false, "should raise TimeOut"),
+            if std::env::var_os("REXPECT_TEST_NESTED").is_some() {
+                // the inner part, run by the outer part below within a session
+                let mut p = spawn("cat", Some(1000))?;
+                p.send_line("inner")?;
+                p.exp_string("inner")?;
+                println!("inner session ok");
+                return Ok(());
+            }
+            let exe = std::env::current_exe().chain_err(|| "cannot find test binary")?;
+            let mut cmd = Command::new(exe);
+            cmd.args(["--exact", "session::tests::test_nested_sessions", "--nocapture"])
+                .env("REXPECT_TEST_NESTED", "1");
+            let mut p = spawn_command(cmd, Some(10000))?;
+            p.exp_string("inner session ok")?;
+            p.exp_string("test result: ok")?;
+            p.exp_eof()?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_nested_sessions failed: {}", e));
+    }
+
+    #[test]
+    fn test_forward_signals() {
+        || -> Result<()> {
+            if let Some(marker) = std::env::var_os("REXPECT_TEST_FORWARD") {
+                // the inner part: gets SIGTERM from the outer part below
+                let mut cmd = Command::new("bash");
+                let script = "trap '' HUP; trap 'echo forwarded >\"$0\"; exit' TERM; echo ready; \
+                              while :; do sleep 0.05; done";
+                cmd.arg("-c").arg(script).arg(marker);
+                let options = SpawnOptions::new().forward_signals(true);
+                let mut p = spawn_with_options(cmd, Some(1000), options)?;
+                p.exp_string("ready")?;
+                println!("inner ready");
+                thread::sleep(time::Duration::from_secs(10));
+                return Ok(());
+            }
+            let dir = tempfile::tempdir().chain_err(|| "cannot create dir")?;
+            let marker = dir.path().join("marker");
+            let exe = std::env::current_exe().chain_err(|| "cannot find test binary")?;
+            let mut cmd = Command::new(exe);
+            cmd.args(["--exact", "session::tests::test_forward_signals", "--nocapture"])
+                .env("REXPECT_TEST_FORWARD", &marker);
+            let mut p = spawn_command(cmd, Some(10000))?;
+            p.exp_string("inner ready")?;
+            p.process.signal(nix::sys::signal::SIGTERM)?;
+            // ends like without forwarding
+            let status = p.process.wait()?;
+            assert_eq!(wait::WaitStatus::Signaled(p.process.child_pid,
+                                                  nix::sys::signal::SIGTERM, false),
+                       status);
+            let start = time::Instant::now();
+            while !marker.exists() && start.elapsed() < time::Duration::from_secs(2) {
+                thread::sleep(time::Duration::from_millis(10));
+            }
+            let forwarded = std::fs::read_to_string(&marker).chain_err(|| "not forwarded")?;
+            assert_eq!("forwarded\n", forwarded);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_forward_signals failed: {}", e));
+    }
 
+    #[test]
+    fn test_attach() {
+        || -> Result<()> {
+            use std::os::unix::io::FromRawFd;
+            // a process in a pty set up without rexpect
+            let pty = nix::pty::openpty(None, None).chain_err(|| "cannot open pty")?;
+            for &fd in &[pty.master, pty.slave] {
+                // other tests spawn processes concurrently
+                let cloexec = nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC);
+                nix::fcntl::fcntl(fd, cloexec).chain_err(|| "cannot set close-on-exec")?;
             }
+            let slave = unsafe { File::from_raw_fd(pty.slave) };
+            let stdio = |f: &File| f.try_clone().map(std::process::Stdio::from);
+            let child = Command::new("cat")
+                .stdin(stdio(&slave).chain_err(|| "cannot dup")?)
+                .stdout(stdio(&slave).chain_err(|| "cannot dup")?)
+                .stderr(stdio(&slave).chain_err(|| "cannot dup")?)
+                .spawn()
+                .chain_err(|| "cannot execute cat")?;
+            drop(slave);
+            let pid = nix::unistd::Pid::from_raw(child.id() as i32);
+            let process = unsafe { PtyProcess::from_raw_parts(pty.master, pid)? };
+            let mut p = attach(process, Some(1000))?;
+            p.send_line("hello")?;
+            p.exp_string("hello")?;
+            p.send_eof()?;
+            p.exp_eof()?;
+            assert_eq!(wait::WaitStatus::Exited(pid, 0), p.process.wait()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_attach failed: {}", e));
+    }
+
+    #[test]
+    fn test_login_shell() {
+        || -> Result<()> {
+            let mut p = spawn_command(crate::process::login_shell(), Some(10000))?;
+            // `m` in `$-`: job control is on
+            p.send_line("case $- in *m*) echo \"term=$TERM jobs=on\";; esac; exit")?;
+            p.exp_string("term=xterm jobs=on")?;
+            p.exp_eof()?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_login_shell failed: {}", e));
+    }
+
+    #[test]
+    fn test_send_slow() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000))?;
+            let start = time::Instant::now();
+            assert_eq!(5, p.send_slow("hans\n", time::Duration::from_millis(20),
+                                      time::Duration::from_millis(10))?);
+            assert!(start.elapsed() >= time::Duration::from_millis(100));
+            assert_eq!("hans", p.read_line()?);
             Ok(())
         }()
-                .unwrap_or_else(|e| panic!("test_timeout failed: {}", e));
+                .unwrap_or_else(|e| panic!("test_send_slow failed: {}", e));
+    }
+
+    #[test]
+    fn test_expect_eof_timeout() {
+        let mut p = spawn("sleep 3", Some(1000)).expect("cannot run sleep 3");
+        match p.exp_eof() {
+            Ok(_) => panic!("should raise Timeout"),
+            Err(Error(ErrorKind::Timeout(_, _, _), _)) => {}
+            Err(_) => panic!("should raise TimeOut"),
+        }
     }
 
     #[test]
@@ -484,15 +2626,118 @@ mod tests {
         || -> Result<()> {
             let mut p = spawn("cat", Some(1000)).expect("cannot run cat");
             p.send_line("Hi")?;
+            // give cat time to echo the whole line, otherwise "Hi" could match
+            // before the third byte arrived
+            std::thread::sleep(std::time::Duration::from_millis(100));
             match p.exp_any(vec![ReadUntil::NBytes(3), ReadUntil::String("Hi".to_string())]) {
                 Ok(s) => assert_eq!(("".to_string(), "Hi\r".to_string()), s),
-                Err(e) => assert!(false, format!("got error: {}", e)),
+                Err(e) => panic!("got error: {}", e),
             }
             Ok(())
         }()
                 .unwrap_or_else(|e| panic!("test_expect_any failed: {}", e));
     }
 
+    #[test]
+    fn test_fail_pattern() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(2000)).expect("cannot run cat");
+            p.add_fail_pattern(ReadUntil::Regex(Regex::new("[Ee]rror").unwrap()));
+            p.send_line("Error: login failed")?;
+            let start = std::time::Instant::now();
+            match p.exp_string("Welcome") {
+                Err(Error(ErrorKind::FailPattern(_, _, _), _)) => {}
+                other => panic!("expected FailPattern error, got {:?}", other),
+            }
+            // right away, not after the timeout
+            assert!(start.elapsed() < std::time::Duration::from_millis(500));
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_fail_pattern failed: {}", e));
+    }
+
+    #[test]
+    fn test_execute_to_writer() {
+        || -> Result<()> {
+            let mut p = spawn_bash(Some(1000))?;
+            p.start_transcript();
+            let mut output = Vec::new();
+            assert_eq!(Capture::Collected("1\r\n2\r\n".to_string()),
+                       p.execute_to_writer("seq 1 2", 100, &mut output)?);
+            assert!(output.is_empty());
+            assert_eq!(Capture::Streamed(4893), p.execute_to_writer("seq 1 1000", 100, &mut output)?);
+            assert_eq!(4893, output.len());
+            assert!(output.ends_with(b"999\r\n1000\r\n"));
+            let transcript = p.transcript().unwrap();
+            assert!(transcript.contains("1\r\n2\r\n"));
+            assert!(transcript.contains("999\r\n1000\r\n"), "{:?}", transcript);
+
+            p.add_fail_pattern(ReadUntil::String("Error".to_string()));
+            let mut output = Vec::new();
+            match p.execute_to_writer("seq 1 200; echo Err''or", 100, &mut output) {
+                Err(Error(ErrorKind::FailPattern(_, _, got), _)) => {
+                    // what wasn't written yet is in the error
+                    output.extend_from_slice(got.as_bytes());
+                }
+                res => panic!("expected FailPattern error, got {:?}", res),
+            }
+            assert!(output.starts_with(b"1\r\n2\r\n"));
+            assert!(output.ends_with(b"199\r\n200\r\nError"));
+            p.wait_for_prompt()?;
+
+            match p.execute_to_writer("exit 3", 100, &mut output) {
+                Err(Error(ErrorKind::EOF(_, _, status), _)) => {
+                    assert!(status.is_some(), "EOF without the process status");
+                }
+                res => panic!("expected EOF error, got {:?}", res),
+            }
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_execute_to_writer failed: {}", e));
+    }
+
+    #[test]
+    fn test_inject_output() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000)).expect("cannot run cat");
+            p.send_line("from cat")?;
+            assert_eq!("from cat", p.read_line()?);
+            p.inject_output(b"from elsewhere");
+            assert_eq!("", p.exp_string("from elsewhere")?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_inject_output failed: {}", e));
+    }
+
+    #[test]
+    fn test_expect_before() {
+        || -> Result<()> {
+            let mut p = spawn("cat", Some(1000)).expect("cannot run cat");
+            let mut pages = 0;
+            p.expect_before(ReadUntil::String("--More--".to_string()), move |s| {
+                pages += 1;
+                s.send_line(&format!("page {}", pages + 1))?;
+                Ok(())
+            });
+            p.send_line("page 1 --More--")?;
+            p.exp_string("page 2")?;
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_expect_before failed: {}", e));
+    }
+
+    #[test]
+    fn test_read_until_idle() {
+        || -> Result<()> {
+            let mut p = spawn_bash(Some(2000))?;
+            p.send_line("echo one; sleep 0.1; echo two")?;
+            let output = p.read_until_idle(std::time::Duration::from_millis(500))?;
+            assert!(output.contains("one") && output.contains("two"), "got {:?}", output);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_read_until_idle failed: {}", e));
+    }
+
     #[test]
     fn test_kill_timeout() {
         || -> Result<()> {
@@ -517,6 +2762,37 @@ mod tests {
                 .unwrap_or_else(|e| panic!("test_bash failed: {}", e));
     }
 
+    #[test]
+    fn test_bash_prompt_nonce() {
+        || -> Result<()> {
+            let mut p = spawn_bash(Some(1000))?;
+            let other = spawn_bash(Some(1000))?;
+            assert!(p.prompt.starts_with("[REXPECT_PROMPT_"), "{}", p.prompt);
+            assert_ne!(p.prompt, other.prompt);
+            // printing the prompt of another session doesn't end the command
+            p.send_line(&format!("echo '{}'; echo done", other.prompt))?;
+            assert_eq!(format!("{}\r\ndone\r\n", other.prompt), p.wait_for_prompt()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_bash_prompt_nonce failed: {}", e));
+    }
+
+    #[test]
+    fn test_spawn_in_shell() {
+        || -> Result<()> {
+            let mut p = spawn_bash(Some(1000))?;
+            let mut program = p.spawn_in_shell("printf '\\e[?1049h> '; read cmd; \
+                                                printf '\\e[?1049l'; echo \"ran $cmd\"")?;
+            program.exp_string("> ")?;
+            program.send_line("quit")?;
+            assert_eq!("ran quit\r\n", program.wait_for_exit()?);
+            p.send_line("echo back")?;
+            assert_eq!("back\r\n", p.wait_for_prompt()?);
+            Ok(())
+        }()
+                .unwrap_or_else(|e| panic!("test_spawn_in_shell failed: {}", e));
+    }
+
     #[test]
     fn test_bash_control_chars() {
         || -> Result<()> {
@@ -525,6 +2801,9 @@ mod tests {
             p.send_control('c')?; // abort: SIGINT
             p.wait_for_prompt()?;
             p.execute("cat <(echo ready) -", "ready")?;
+            p.send_control(ControlCode::EndOfText)?; // same as above
+            p.wait_for_prompt()?;
+            p.execute("cat <(echo ready) -", "ready")?;
             p.send_control('z')?; // suspend:SIGTSTPcon
             p.exp_regex(r"(Stopped|suspended)\s+cat .*")?;
             p.send_line("fg")?;